@@ -1,17 +1,23 @@
 use crate::auth::auth_filter;
 use crate::endpoints;
+use crate::papers::{JobId, Jobs};
 use crate::prelude::*;
 use futures::{FutureExt, TryFutureExt};
 use warp::{
     filters::{
         body::json,
-        method::{get2, head, post2},
-        path::{end, path},
+        header,
+        method::{delete2, get2, head, post2},
+        path::{end, param, path, tail, Tail},
+        query::query,
         BoxedFilter,
     },
     Filter,
 };
 
+/// Repeated submissions with the same value for this header return the original job.
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
 fn config_filter(config: Arc<Config>) -> impl Fn() -> BoxedFilter<(Arc<Config>,)> {
     move || {
         let config = Arc::clone(&config);
@@ -19,11 +25,20 @@ fn config_filter(config: Arc<Config>) -> impl Fn() -> BoxedFilter<(Arc<Config>,)
     }
 }
 
+fn jobs_filter(jobs: Jobs) -> impl Fn() -> BoxedFilter<(Jobs,)> {
+    move || {
+        let jobs = jobs.clone();
+        warp::any().map(move || jobs.clone()).boxed()
+    }
+}
+
 /// Create a [warp BoxedFilter](warp::filters::BoxedFilter) based on the provided configuration.
-pub fn app(config: Arc<Config>) -> BoxedFilter<(impl warp::Reply,)> {
+/// Accepted jobs are handed to `jobs`.
+pub fn app(config: Arc<Config>, jobs: Jobs) -> BoxedFilter<(impl warp::Reply,)> {
     // Authentication if enabled.
     let auth_filter = config.auth.clone().map(auth_filter);
     let with_config = config_filter(config);
+    let with_jobs = jobs_filter(jobs);
 
     let base = if let Some(filter) = auth_filter {
         warp::any().and(filter).boxed()
@@ -38,14 +53,34 @@ pub fn app(config: Arc<Config>) -> BoxedFilter<(impl warp::Reply,)> {
         .and(head().or(get2()).unify())
         .map(|| "OK");
 
+    // GET /files/*
+    //
+    // This is not behind the authentication, the URLs are signed instead.
+    let file = path("files")
+        .and(tail())
+        .and(get2())
+        .and(query::<endpoints::FileQuery>())
+        .and(with_config())
+        .and_then(|tail: Tail, query, config| {
+            let key = percent_encoding::percent_decode_str(tail.as_str())
+                .decode_utf8_lossy()
+                .into_owned();
+
+            endpoints::file(key, query, config)
+                .map_err(EndpointError::into_rejection)
+                .boxed()
+                .compat()
+        });
+
     // POST /merge
     let merge = path("merge")
         .and(end())
         .and(post2())
         .and(json())
-        .and(with_config())
-        .and_then(|merge_spec, config| {
-            endpoints::merge(merge_spec, config)
+        .and(header::optional(IDEMPOTENCY_KEY_HEADER))
+        .and(with_jobs())
+        .and_then(|merge_spec, idempotency_key, jobs| {
+            endpoints::merge(merge_spec, idempotency_key, jobs)
                 .map_err(EndpointError::into_rejection)
                 .boxed()
                 .compat()
@@ -56,9 +91,11 @@ pub fn app(config: Arc<Config>) -> BoxedFilter<(impl warp::Reply,)> {
         .and(end())
         .and(post2())
         .and(json())
+        .and(header::optional(IDEMPOTENCY_KEY_HEADER))
         .and(with_config())
-        .and_then(|document_spec, config| {
-            endpoints::submit(document_spec, config)
+        .and(with_jobs())
+        .and_then(|document_spec, idempotency_key, config, jobs| {
+            endpoints::submit(document_spec, idempotency_key, config, jobs)
                 .map_err(EndpointError::into_rejection)
                 .boxed()
                 .compat()
@@ -77,9 +114,58 @@ pub fn app(config: Arc<Config>) -> BoxedFilter<(impl warp::Reply,)> {
                 .compat()
         });
 
-    let routes = merge.or(submit).or(preview);
+    // POST /render
+    let render = path("render")
+        .and(end())
+        .and(post2())
+        .and(json())
+        .and(with_config())
+        .and(with_jobs())
+        .and_then(|render_spec, config, jobs| {
+            endpoints::render(render_spec, config, jobs)
+                .map_err(EndpointError::into_rejection)
+                .boxed()
+                .compat()
+        });
+
+    // GET /jobs/:id
+    let job_status = path("jobs")
+        .and(param::<JobId>())
+        .and(end())
+        .and(get2())
+        .and(with_jobs())
+        .and_then(|job_id, jobs| {
+            endpoints::job_status(job_id, jobs)
+                .map_err(EndpointError::into_rejection)
+                .boxed()
+                .compat()
+        });
+
+    // DELETE /jobs/:id
+    let cancel_job = path("jobs")
+        .and(param::<JobId>())
+        .and(end())
+        .and(delete2())
+        .and(with_jobs())
+        .and_then(|job_id, jobs| {
+            endpoints::cancel_job(job_id, jobs)
+                .map_err(EndpointError::into_rejection)
+                .boxed()
+                .compat()
+        });
+
+    let routes = merge
+        .or(submit)
+        .or(preview)
+        .or(render)
+        .or(job_status)
+        .or(cancel_job);
 
-    healthz.or(base.and(routes)).recover(recover).boxed()
+    healthz
+        .or(file)
+        .or(base.and(routes))
+        .recover(recover)
+        .boxed()
 }
 
 fn recover(rejection: warp::Rejection) -> Result<Response, warp::Rejection> {
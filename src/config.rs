@@ -1,12 +1,40 @@
 use crate::human_size::Bytes;
+use crate::papers::{Engines, WorkspaceUpload};
+use crate::storage::credentials::RefreshingCredentials;
+use crate::storage::{KeyPattern, LocalStorage, MemoryStorage, Retention, S3Storage, Storage};
+use crate::utils::archive::Compression;
+use crate::utils::encryption::WorkspaceEncryption;
+use crate::utils::process::ResourceLimits;
+use crate::utils::sandbox::Sandbox;
+use failure::{format_err, ResultExt};
 use rusoto_core::region::Region;
 use slog::{o, warn, Logger};
 use sloggers::types::Severity;
 use sloggers::Build;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 const MAX_ASSET_SIZE_DEFAULT: u32 = 10_000_000;
 const MAX_ASSETS_PER_DOCUMENT_DEFAULT: u32 = 20;
+const MAX_CONCURRENT_JOBS_DEFAULT: usize = 4;
+const MAX_QUEUED_JOBS_DEFAULT: usize = 100;
+const CALLBACK_MAX_ATTEMPTS_DEFAULT: u32 = 5;
+const CALLBACK_INITIAL_BACKOFF_DEFAULT: u64 = 2;
+const IDEMPOTENCY_WINDOW_DEFAULT: u64 = 24 * 3600;
+const RENDER_TIMEOUT_DEFAULT: u64 = 60;
+const URL_EXPIRATION_DEFAULT: u64 = 24 * 3600;
+const RETENTION_INTERVAL_DEFAULT: u64 = 3600;
+const LATEX_MAX_PASSES_DEFAULT: u32 = 5;
+const LATEX_TIMEOUT_DEFAULT: u64 = 120;
+const PDFUNITE_TIMEOUT_DEFAULT: u64 = 60;
+const CONVERT_TIMEOUT_DEFAULT: u64 = 60;
+const PDFINFO_TIMEOUT_DEFAULT: u64 = 30;
+const ENCRYPTION_TIMEOUT_DEFAULT: u64 = 300;
+/// Presigned S3 URLs cannot be valid for more than a week.
+const S3_MAX_URL_EXPIRATION: u64 = 7 * 24 * 3600;
 
 fn max_assets_per_document(logger: &Logger) -> u32 {
     match std::env::var("PAPERS_MAX_ASSETS_PER_DOCUMENT").map(|max| max.parse()) {
@@ -22,21 +50,82 @@ fn max_assets_per_document(logger: &Logger) -> u32 {
     }
 }
 
-fn aws_region() -> rusoto_core::region::Region {
-    std::env::var("PAPERS_LOCALSTACK_ENDPOINT")
-        .and_then(|ep| {
-            Ok(rusoto_core::region::Region::Custom {
-                endpoint: ep,
-                name: "localstack".to_owned(),
-            })
-        })
-        .or_else::<rusoto_core::region::Region, _>(|_| {
-            Ok(std::env::var("PAPERS_AWS_REGION")
-                .expect("The PAPERS_AWS_REGION environment variable was not provided")
-                .parse::<rusoto_core::region::Region>()
-                .expect("The provided AWS region is not valid"))
-        })
-        .expect("Something went wrong with processing the AWS region")
+/// Parse the `name` environment variable, falling back to `default` when it is missing or
+/// invalid.
+fn env_var_or<T: FromStr>(logger: &Logger, name: &str, default: T) -> T {
+    match std::env::var(name).map(|value| value.parse()) {
+        Ok(Ok(value)) => value,
+        Ok(Err(_)) => {
+            warn!(logger, "Unable to parse {} environment variable", name);
+            default
+        }
+        _ => default,
+    }
+}
+
+/// A retention period in seconds, if it is set.
+fn retention_period(name: &str) -> Result<Option<Duration>, failure::Error> {
+    match std::env::var(name) {
+        Ok(value) => {
+            let seconds = value
+                .parse::<u64>()
+                .with_context(|_| format!("{} should be a duration in seconds", name))?;
+            Ok(Some(Duration::from_secs(seconds)))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// A resource limit, if it is set.
+fn resource_limit(
+    name: &str,
+    parse: impl Fn(&str) -> Option<u64>,
+) -> Result<Option<u64>, failure::Error> {
+    match std::env::var(name) {
+        Ok(value) => parse(&value)
+            .map(Some)
+            .ok_or_else(|| format_err!("Invalid {}: {:?}", name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_bytes(value: &str) -> Option<u64> {
+    Bytes::from_str(value).ok().map(|bytes| u64::from(bytes.0))
+}
+
+fn aws_region() -> Result<rusoto_core::region::Region, failure::Error> {
+    if let Ok(endpoint) = std::env::var("PAPERS_LOCALSTACK_ENDPOINT") {
+        return Ok(rusoto_core::region::Region::Custom {
+            endpoint,
+            name: "localstack".to_owned(),
+        });
+    }
+
+    let region = std::env::var("PAPERS_AWS_REGION")
+        .context("The PAPERS_AWS_REGION environment variable was not provided")?;
+
+    Ok(region
+        .parse::<rusoto_core::region::Region>()
+        .context("The provided AWS region is not valid")?)
+}
+
+/// The storage for `PAPERS_STORAGE=local`.
+fn local_storage(logger: &Logger) -> Result<LocalStorage, failure::Error> {
+    use rand::Rng;
+
+    let root = std::env::var("PAPERS_STORAGE_DIR")
+        .context("The PAPERS_STORAGE_DIR environment variable was not provided")?;
+    let base_url = std::env::var("PAPERS_FILES_BASE_URL")
+        .context("The PAPERS_FILES_BASE_URL environment variable was not provided")?;
+    let secret = std::env::var("PAPERS_FILES_SECRET").unwrap_or_else(|_| {
+        warn!(
+            logger,
+            "PAPERS_FILES_SECRET is not set, the file URLs will stop working on restart."
+        );
+        hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+    });
+
+    LocalStorage::new(PathBuf::from(root), &base_url, secret)
 }
 
 /// Relies on the PAPERS_LOG_LEVEL env variable.
@@ -64,15 +153,98 @@ pub struct S3Config {
     pub bucket: String,
     /// The AWS region of the bucket.
     pub region: Region,
-    /// The expiration time of presigned URLs in seconds.
-    pub expiration_time: u32,
-    /// The AWS credentials.
-    pub credentials: rusoto_credential::AwsCredentials,
-    /// The AWS credentials provider.
-    credentials_provider: rusoto_credential::EnvironmentProvider,
+    /// The AWS credentials provider, which caches the credentials until they expire.
+    credentials_provider: Arc<RefreshingCredentials>,
+    /// The server-side encryption of the uploaded objects.
+    pub server_side_encryption: Option<ServerSideEncryption>,
+    /// The default storage class of the uploaded objects.
+    pub storage_class: Option<String>,
+    /// The default tags of the uploaded objects.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// How S3 encrypts the objects at rest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerSideEncryption {
+    /// With keys managed by S3 (SSE-S3).
+    S3,
+    /// With a KMS key (SSE-KMS), or the default KMS key of the account when no key id is given.
+    Kms {
+        /// The id or ARN of the key.
+        key_id: Option<String>,
+    },
+}
+
+impl ServerSideEncryption {
+    /// Reads `PAPERS_S3_SSE` and `PAPERS_S3_SSE_KMS_KEY_ID`.
+    fn from_env() -> Result<Option<Self>, failure::Error> {
+        let algorithm = std::env::var("PAPERS_S3_SSE").ok();
+        let key_id = std::env::var("PAPERS_S3_SSE_KMS_KEY_ID").ok();
+
+        match (algorithm.as_ref().map(String::as_str), key_id) {
+            (None, None) => Ok(None),
+            (Some("AES256"), None) => Ok(Some(ServerSideEncryption::S3)),
+            (Some("aws:kms"), key_id) | (None, key_id @ Some(_)) => {
+                Ok(Some(ServerSideEncryption::Kms { key_id }))
+            }
+            (Some("AES256"), Some(_)) => Err(format_err!(
+                "PAPERS_S3_SSE_KMS_KEY_ID can only be used with PAPERS_S3_SSE=aws:kms"
+            )),
+            (Some(other), _) => Err(format_err!(
+                "Unknown PAPERS_S3_SSE {:?}, expected AES256 or aws:kms",
+                other
+            )),
+        }
+    }
+}
+
+/// Parse tags in the format of the S3 `x-amz-tagging` header: `key1=value1&key2=value2`, with
+/// URL-encoded keys and values.
+fn parse_tags(tags: &str) -> Result<BTreeMap<String, String>, failure::Error> {
+    let decode = |part: &str| -> Result<String, failure::Error> {
+        let part = part.replace('+', " ");
+        let decoded = percent_encoding::percent_decode_str(&part).decode_utf8()?;
+        Ok(decoded.into_owned())
+    };
+
+    tags.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = decode(parts.next().unwrap_or_default())?;
+            let value = decode(parts.next().unwrap_or_default())?;
+
+            if key.is_empty() {
+                return Err(format_err!("Empty tag key in {:?}", tags));
+            }
+
+            Ok((key, value))
+        })
+        .collect()
 }
 
 impl S3Config {
+    /// Read the S3 configuration from the environment variables.
+    pub fn from_env() -> Result<S3Config, failure::Error> {
+        let credentials_provider = RefreshingCredentials::new()
+            .context("Could not create the AWS credentials provider")?;
+
+        Ok(S3Config {
+            bucket: std::env::var("PAPERS_S3_BUCKET")
+                .context("The PAPERS_S3_BUCKET environment variable was not provided")?,
+            credentials_provider: Arc::new(credentials_provider),
+            region: aws_region()?,
+            server_side_encryption: ServerSideEncryption::from_env()?,
+            storage_class: std::env::var("PAPERS_S3_STORAGE_CLASS").ok(),
+            tags: parse_tags(&std::env::var("PAPERS_S3_TAGS").unwrap_or_default())
+                .context("PAPERS_S3_TAGS should be formatted like key1=value1&key2=value2")?,
+        })
+    }
+
+    pub(crate) fn credentials_provider(&self) -> &RefreshingCredentials {
+        &self.credentials_provider
+    }
+
     pub(crate) fn client(&self) -> rusoto_s3::S3Client {
         rusoto_s3::S3Client::new_with(
             rusoto_core::request::HttpClient::new().unwrap(),
@@ -92,10 +264,70 @@ pub struct Config {
     pub max_assets_per_document: u32,
     /// Limits the size of the assets downloaded by the service, including templates
     pub max_asset_size: u32,
+    /// The maximum number of jobs (renders and merges) running at the same time
+    pub max_concurrent_jobs: usize,
+    /// The maximum number of jobs waiting for a worker before new ones are rejected
+    pub max_queued_jobs: usize,
+    /// Where accepted jobs are persisted so they survive a restart. Jobs only live in memory
+    /// when this is not set.
+    pub data_dir: Option<PathBuf>,
+    /// How many times posting to a callback URL is attempted before giving up
+    pub callback_max_attempts: u32,
+    /// The delay before the first retry of a failed callback. It doubles after every attempt.
+    pub callback_backoff: Duration,
+    /// The secret used to sign the requests to the callback URLs, so they can be authenticated
+    pub callback_secret: Option<String>,
+    /// How long an idempotency key keeps pointing to the job it was first submitted with
+    pub idempotency_window: Duration,
+    /// How long a synchronous render (`POST /render`) may take before it is aborted
+    pub render_timeout: Duration,
     /// The root logger for the application
     pub logger: Logger,
-    /// The S3 configuration
-    pub s3: S3Config,
+    /// Where the generated documents and the workspaces are stored
+    pub storage: Arc<dyn Storage>,
+    /// How long the URLs to the stored files are valid, and the longest validity a request can
+    /// ask for
+    pub url_expiration: Duration,
+    /// When set, the URLs to the stored files are this base URL followed by the key, instead of
+    /// expiring URLs from the storage
+    pub public_base_url: Option<String>,
+    /// The layout of the keys of the stored files
+    pub key_pattern: KeyPattern,
+    /// The LaTeX engines the requests can choose, and how they are run
+    pub engines: Engines,
+    /// The most times the engine runs for a document, until its references settle
+    pub latex_max_passes: u32,
+    /// Whether latexmk drives the engine and the bibliography and index tools
+    pub latexmk: bool,
+    /// How long each run of the LaTeX engine and of its tools may take before it is killed
+    pub latex_timeout: Duration,
+    /// How long merging PDFs with `pdfunite` may take before it is killed
+    pub pdfunite_timeout: Duration,
+    /// How long converting an image with `convert` may take before it is killed
+    pub convert_timeout: Duration,
+    /// How long counting the pages of a PDF with `pdfinfo` may take before it is killed
+    pub pdfinfo_timeout: Duration,
+    /// How long encrypting a workspace archive may take before `age` or `gpg` is killed
+    pub encryption_timeout: Duration,
+    /// The resource limits of LaTeX, `pdfunite`, `convert`, `pdfinfo` and the encryption
+    pub resource_limits: ResourceLimits,
+    /// The sandbox of LaTeX, `pdfunite`, `convert`, `pdfinfo` and the encryption. They run as
+    /// is when not set.
+    pub sandbox: Option<Sandbox>,
+    /// When the workspaces of the jobs are uploaded, unless a request says otherwise
+    pub workspace_upload: WorkspaceUpload,
+    /// Whether the downloaded template and assets are left out of the workspace archives
+    pub workspace_exclude_assets: bool,
+    /// How the workspace archives are compressed
+    pub workspace_compression: Compression,
+    /// Who the workspace archives are encrypted for. They are uploaded as is when not set.
+    pub workspace_encryption: Option<WorkspaceEncryption>,
+    /// How long the stored files are kept before the sweeper deletes them
+    pub retention: Retention,
+    /// How often the sweeper looks for expired files
+    pub retention_interval: Duration,
+    /// Whether the sweeper only logs what it would delete
+    pub retention_dry_run: bool,
 }
 
 impl Config {
@@ -106,24 +338,40 @@ impl Config {
             logger: build_logger(),
             max_asset_size: MAX_ASSET_SIZE_DEFAULT,
             max_assets_per_document: MAX_ASSETS_PER_DOCUMENT_DEFAULT,
-            s3: S3Config {
-                bucket: "walrus".into(),
-                credentials: rusoto_credential::AwsCredentials::new("a", "b", None, None),
-                credentials_provider: rusoto_credential::EnvironmentProvider::default(),
-                expiration_time: 3600,
-                region: rusoto_core::region::Region::Custom {
-                    endpoint: "http://s3.localhost".into(),
-                    name: "local_s3".into(),
-                },
-            },
+            max_concurrent_jobs: MAX_CONCURRENT_JOBS_DEFAULT,
+            max_queued_jobs: MAX_QUEUED_JOBS_DEFAULT,
+            data_dir: None,
+            callback_max_attempts: 1,
+            callback_backoff: Duration::from_millis(10),
+            callback_secret: None,
+            idempotency_window: Duration::from_secs(IDEMPOTENCY_WINDOW_DEFAULT),
+            render_timeout: Duration::from_secs(RENDER_TIMEOUT_DEFAULT),
+            storage: Arc::new(MemoryStorage::new()),
+            url_expiration: Duration::from_secs(URL_EXPIRATION_DEFAULT),
+            public_base_url: None,
+            key_pattern: KeyPattern::default(),
+            engines: Engines::default(),
+            latex_max_passes: LATEX_MAX_PASSES_DEFAULT,
+            latexmk: false,
+            latex_timeout: Duration::from_secs(LATEX_TIMEOUT_DEFAULT),
+            pdfunite_timeout: Duration::from_secs(PDFUNITE_TIMEOUT_DEFAULT),
+            convert_timeout: Duration::from_secs(CONVERT_TIMEOUT_DEFAULT),
+            pdfinfo_timeout: Duration::from_secs(PDFINFO_TIMEOUT_DEFAULT),
+            encryption_timeout: Duration::from_secs(ENCRYPTION_TIMEOUT_DEFAULT),
+            resource_limits: ResourceLimits::default(),
+            sandbox: None,
+            workspace_upload: WorkspaceUpload::Always,
+            workspace_exclude_assets: false,
+            workspace_compression: Compression::None,
+            workspace_encryption: None,
+            retention: Retention::default(),
+            retention_interval: Duration::from_secs(RETENTION_INTERVAL_DEFAULT),
+            retention_dry_run: false,
         }
     }
 
     /// The normal way to construct a `Config`, reading from environment variables.
-    pub fn from_env() -> Config {
-        use futures01::Future;
-        use rusoto_credential::ProvideAwsCredentials;
-
+    pub fn from_env() -> Result<Config, failure::Error> {
         let max_asset_size = std::env::var("PAPERS_MAX_ASSET_SIZE")
             .map_err(|_| ())
             .and_then(|s| Bytes::from_str(&s))
@@ -134,35 +382,168 @@ impl Config {
 
         let logger = build_logger();
         let max_assets_per_document = max_assets_per_document(&logger);
+        let max_concurrent_jobs = env_var_or(
+            &logger,
+            "PAPERS_MAX_CONCURRENT_JOBS",
+            MAX_CONCURRENT_JOBS_DEFAULT,
+        )
+        .max(1);
+        let max_queued_jobs =
+            env_var_or(&logger, "PAPERS_MAX_QUEUED_JOBS", MAX_QUEUED_JOBS_DEFAULT);
+        let data_dir = std::env::var("PAPERS_DATA_DIR").ok().map(PathBuf::from);
+        let callback_max_attempts = env_var_or(
+            &logger,
+            "PAPERS_CALLBACK_MAX_ATTEMPTS",
+            CALLBACK_MAX_ATTEMPTS_DEFAULT,
+        )
+        .max(1);
+        let callback_backoff = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_CALLBACK_INITIAL_BACKOFF",
+            CALLBACK_INITIAL_BACKOFF_DEFAULT,
+        ));
+        let callback_secret = std::env::var("PAPERS_CALLBACK_SECRET").ok();
+        let idempotency_window = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_IDEMPOTENCY_WINDOW",
+            IDEMPOTENCY_WINDOW_DEFAULT,
+        ));
+        let render_timeout = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_RENDER_TIMEOUT",
+            RENDER_TIMEOUT_DEFAULT,
+        ));
 
-        let expiration_time: u32 = std::env::var("PAPERS_S3_EXPIRATION_TIME")
-            .unwrap_or_else(|_| "86400".to_string()) // one day
-            .parse()
-            .expect("PAPERS_S3_EXPIRATION_TIME should be a duration in seconds");
+        let url_expiration = Duration::from_secs(
+            std::env::var("PAPERS_S3_EXPIRATION_TIME")
+                .unwrap_or_else(|_| URL_EXPIRATION_DEFAULT.to_string())
+                .parse::<u64>()
+                .context("PAPERS_S3_EXPIRATION_TIME should be a duration in seconds")?,
+        );
+        let public_base_url = std::env::var("PAPERS_PUBLIC_BASE_URL")
+            .ok()
+            .map(|base_url| base_url.trim_end_matches('/').to_owned());
 
-        let credentials_provider = rusoto_credential::EnvironmentProvider::with_prefix("PAPERS");
+        let storage: Arc<dyn Storage> = match std::env::var("PAPERS_STORAGE")
+            .unwrap_or_else(|_| "s3".to_owned())
+            .as_str()
+        {
+            "s3" => {
+                if public_base_url.is_none() && url_expiration.as_secs() > S3_MAX_URL_EXPIRATION {
+                    return Err(format_err!(
+                        "PAPERS_S3_EXPIRATION_TIME cannot be more than {} seconds with the s3 \
+                         storage, since presigned URLs are valid for a week at most",
+                        S3_MAX_URL_EXPIRATION
+                    ));
+                }
 
-        let credentials = credentials_provider
-            .credentials()
-            .wait()
-            .expect("error reading AWS credentials from environment");
+                Arc::new(S3Storage::new(S3Config::from_env()?, logger.clone()))
+            }
+            "local" => Arc::new(local_storage(&logger)?),
+            "memory" => Arc::new(MemoryStorage::new()),
+            other => return Err(format_err!("Unknown PAPERS_STORAGE: {:?}", other)),
+        };
 
-        let s3 = S3Config {
-            bucket: std::env::var("PAPERS_S3_BUCKET")
-                .expect("The PAPERS_S3_BUCKET environment variable was not provided"),
-            credentials,
-            credentials_provider,
-            region: aws_region(),
-            expiration_time,
+        let key_pattern = match std::env::var("PAPERS_KEY_PATTERN") {
+            Ok(pattern) => KeyPattern::parse(&pattern)?,
+            Err(_) => KeyPattern::default(),
         };
 
-        Config {
+        let engines = Engines::from_env()?;
+        let latex_max_passes =
+            env_var_or(&logger, "PAPERS_LATEX_MAX_PASSES", LATEX_MAX_PASSES_DEFAULT).max(1);
+        let latexmk = env_var_or(&logger, "PAPERS_LATEXMK", false);
+        let latex_timeout = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_LATEX_TIMEOUT",
+            LATEX_TIMEOUT_DEFAULT,
+        ));
+        let pdfunite_timeout = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_PDFUNITE_TIMEOUT",
+            PDFUNITE_TIMEOUT_DEFAULT,
+        ));
+        let convert_timeout = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_CONVERT_TIMEOUT",
+            CONVERT_TIMEOUT_DEFAULT,
+        ));
+        let pdfinfo_timeout = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_PDFINFO_TIMEOUT",
+            PDFINFO_TIMEOUT_DEFAULT,
+        ));
+        let encryption_timeout = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_ENCRYPTION_TIMEOUT",
+            ENCRYPTION_TIMEOUT_DEFAULT,
+        ));
+        let resource_limits = ResourceLimits {
+            cpu_seconds: resource_limit("PAPERS_RLIMIT_CPU", |value| value.parse().ok())?,
+            memory: resource_limit("PAPERS_RLIMIT_MEMORY", parse_bytes)?,
+            file_size: resource_limit("PAPERS_RLIMIT_FILE_SIZE", parse_bytes)?,
+            open_files: resource_limit("PAPERS_RLIMIT_OPEN_FILES", |value| value.parse().ok())?,
+        };
+        let sandbox = Sandbox::from_env()?;
+
+        let workspace_upload = match std::env::var("PAPERS_WORKSPACE_UPLOAD") {
+            Ok(policy) => policy.parse()?,
+            Err(_) => WorkspaceUpload::Always,
+        };
+        let workspace_exclude_assets =
+            env_var_or(&logger, "PAPERS_WORKSPACE_EXCLUDE_ASSETS", false);
+        let workspace_compression = match std::env::var("PAPERS_WORKSPACE_COMPRESSION") {
+            Ok(compression) => compression.parse()?,
+            Err(_) => Compression::None,
+        };
+        let workspace_encryption = WorkspaceEncryption::from_env()?;
+
+        let retention = Retention {
+            success: retention_period("PAPERS_SUCCESS_RETENTION")?,
+            failure: retention_period("PAPERS_FAILURE_RETENTION")?,
+        };
+        let retention_interval = Duration::from_secs(env_var_or(
+            &logger,
+            "PAPERS_RETENTION_INTERVAL",
+            RETENTION_INTERVAL_DEFAULT,
+        ));
+        let retention_dry_run = env_var_or(&logger, "PAPERS_RETENTION_DRY_RUN", false);
+
+        Ok(Config {
             auth,
             logger,
             max_asset_size,
             max_assets_per_document,
-            s3,
-        }
+            max_concurrent_jobs,
+            max_queued_jobs,
+            data_dir,
+            callback_max_attempts,
+            callback_backoff,
+            callback_secret,
+            idempotency_window,
+            render_timeout,
+            storage,
+            url_expiration,
+            public_base_url,
+            key_pattern,
+            engines,
+            latex_max_passes,
+            latexmk,
+            latex_timeout,
+            pdfunite_timeout,
+            convert_timeout,
+            pdfinfo_timeout,
+            encryption_timeout,
+            resource_limits,
+            sandbox,
+            workspace_upload,
+            workspace_exclude_assets,
+            workspace_compression,
+            workspace_encryption,
+            retention,
+            retention_interval,
+            retention_dry_run,
+        })
     }
 
     /// Return a new `Config` with the specified auth secret.
@@ -180,6 +561,59 @@ impl Config {
             ..self
         }
     }
+
+    /// Set `max_concurrent_jobs` and return `self`.
+    pub fn with_max_concurrent_jobs(self, max_concurrent_jobs: usize) -> Config {
+        Config {
+            max_concurrent_jobs,
+            ..self
+        }
+    }
+
+    /// Set `max_queued_jobs` and return `self`.
+    pub fn with_max_queued_jobs(self, max_queued_jobs: usize) -> Config {
+        Config {
+            max_queued_jobs,
+            ..self
+        }
+    }
+
+    /// Set `callback_max_attempts` and return `self`.
+    pub fn with_callback_max_attempts(self, callback_max_attempts: u32) -> Config {
+        Config {
+            callback_max_attempts,
+            ..self
+        }
+    }
+
+    /// Set `storage` and return `self`.
+    pub fn with_storage(self, storage: Arc<dyn Storage>) -> Config {
+        Config { storage, ..self }
+    }
+
+    /// Set `public_base_url` and return `self`.
+    pub fn with_public_base_url(self, public_base_url: String) -> Config {
+        Config {
+            public_base_url: Some(public_base_url),
+            ..self
+        }
+    }
+
+    /// Set `key_pattern` and return `self`.
+    pub fn with_key_pattern(self, key_pattern: KeyPattern) -> Config {
+        Config {
+            key_pattern,
+            ..self
+        }
+    }
+
+    /// Set `data_dir` and return `self`.
+    pub fn with_data_dir(self, data_dir: PathBuf) -> Config {
+        Config {
+            data_dir: Some(data_dir),
+            ..self
+        }
+    }
 }
 
 #[cfg(test)]
@@ -187,12 +621,23 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_parse_tags() {
+        let tags = parse_tags("team=billing&retention=30%20days&empty=").unwrap();
+
+        assert_eq!(tags["team"], "billing");
+        assert_eq!(tags["retention"], "30 days");
+        assert_eq!(tags["empty"], "");
+        assert!(parse_tags("").unwrap().is_empty());
+        assert!(parse_tags("=value").is_err());
+    }
+
     #[test]
     fn test_aws_region_localstack() {
         std::env::set_var("PAPERS_LOCALSTACK_ENDPOINT", "my-endpoint");
 
         assert_eq!(
-            aws_region(),
+            aws_region().unwrap(),
             rusoto_core::region::Region::Custom {
                 endpoint: "my-endpoint".to_owned(),
                 name: "localstack".to_owned()
@@ -204,6 +649,6 @@ mod tests {
     fn test_aws_region_env() {
         std::env::set_var("PAPERS_AWS_REGION", "us-east-1");
 
-        assert_eq!(aws_region(), rusoto_core::region::Region::UsEast1,)
+        assert_eq!(aws_region().unwrap(), rusoto_core::region::Region::UsEast1,)
     }
 }
@@ -1,7 +1,13 @@
+mod files;
+mod jobs;
 mod merge;
 mod preview;
+mod render;
 mod submit;
 
+pub(crate) use files::{file, FileQuery};
+pub(crate) use jobs::{cancel_job, job_status};
 pub(crate) use merge::merge;
 pub(crate) use preview::preview;
+pub(crate) use render::render;
 pub(crate) use submit::submit;
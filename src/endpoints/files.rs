@@ -0,0 +1,63 @@
+use crate::prelude::*;
+use futures::compat::Future01CompatExt;
+use serde::Deserialize;
+
+/// The query string of the URLs handed out by the local storage.
+#[derive(Deserialize, Debug)]
+pub(crate) struct FileQuery {
+    expires: i64,
+    signature: String,
+}
+
+/// Serve a file from the storage, if the URL is validly signed and not expired.
+pub(crate) async fn file(
+    key: String,
+    query: FileQuery,
+    config: Arc<Config>,
+) -> Result<Response, EndpointError> {
+    let not_found = || EndpointError::NotFound {
+        cause: format_err!("No such file, or the link expired."),
+    };
+
+    let path = config
+        .storage
+        .signed_path(&key, query.expires, &query.signature)
+        .ok_or_else(not_found)?;
+
+    let file = match tokio::fs::File::open(path.clone()).compat().await {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(err) => return Err(err.into()),
+    };
+    let (file, metadata) = file.metadata().compat().await?;
+
+    let filename = path
+        .file_name()
+        .map(|filename| filename.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    // The file is streamed, so large workspaces are not read into memory.
+    let body = {
+        use futures01::Stream;
+        use tokio::codec::{BytesCodec, FramedRead};
+
+        FramedRead::new(file, BytesCodec::new()).map(|bytes| bytes.freeze())
+    };
+
+    let mut response = http::Response::new(hyper::Body::wrap_stream(body));
+    response.headers_mut().insert(
+        http::header::CONTENT_LENGTH,
+        http::header::HeaderValue::from(metadata.len()),
+    );
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::header::HeaderValue::from_static(crate::storage::content_type(&filename)),
+    );
+    response.headers_mut().insert(
+        http::header::CONTENT_DISPOSITION,
+        http::header::HeaderValue::from_str(&crate::storage::content_disposition(&filename))
+            .map_err(failure::Error::from)?,
+    );
+
+    Ok(response)
+}
@@ -0,0 +1,16 @@
+use crate::papers::{JobId, Jobs};
+use crate::prelude::*;
+
+pub(crate) async fn job_status(job_id: JobId, jobs: Jobs) -> Result<Response, EndpointError> {
+    let status = jobs.get(&job_id).ok_or_else(|| EndpointError::NotFound {
+        cause: format_err!("No job with id {}", job_id),
+    })?;
+
+    Ok(json_response(&status)?)
+}
+
+pub(crate) async fn cancel_job(job_id: JobId, jobs: Jobs) -> Result<Response, EndpointError> {
+    let status = jobs.cancel(&job_id)?;
+
+    Ok(json_response(&status)?)
+}
@@ -1,16 +1,15 @@
-use crate::papers::Merger;
+use crate::papers::{JobSpec, Jobs};
 use crate::prelude::*;
-use futures::{FutureExt, TryFutureExt};
+use serde_json::json;
 
-pub(crate) async fn merge(merge_spec: MergeSpec, config: Arc<Config>) -> Result<Response, EndpointError> {
+pub(crate) async fn merge(
+    merge_spec: MergeSpec,
+    idempotency_key: Option<String>,
+    jobs: Jobs,
+) -> Result<Response, EndpointError> {
     merge_spec.validate()?;
 
-    tokio::executor::spawn(
-        Merger::new(config, merge_spec)?
-            .merge_documents()
-            .boxed()
-            .compat(),
-    );
+    let job_id = jobs.submit(JobSpec::Merge(merge_spec), idempotency_key)?;
 
-    Ok(empty_response())
+    Ok(json_response(&json!({ "job_id": job_id }))?)
 }
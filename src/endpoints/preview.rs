@@ -1,10 +1,10 @@
 use crate::prelude::*;
-use crate::papers::Renderer;
+use crate::papers::{Job, Renderer};
 
 pub(crate) async fn preview(document_spec: DocumentSpec, config: Arc<Config>) -> Result<Response, EndpointError> {
     document_spec.validate(&config)?;
 
-    let mut renderer = Renderer::new(config, document_spec)?;
+    let mut renderer = Renderer::new(config, Job::untracked(), document_spec)?;
     let populated_template = renderer.preview().await?;
 
     Ok(http::Response::new(populated_template.into()))
@@ -0,0 +1,75 @@
+use crate::papers::{ErrorCode, Job, JobError, Jobs, RenderSpec, Renderer};
+use crate::prelude::*;
+use futures::compat::*;
+use futures::{FutureExt, TryFutureExt};
+
+/// Render a document and respond with the PDF, without going through S3 or a callback. The
+/// rendering takes one of the workers of `jobs`, and is rejected when they are all busy.
+pub(crate) async fn render(
+    render_spec: RenderSpec,
+    config: Arc<Config>,
+    jobs: Jobs,
+) -> Result<Response, EndpointError> {
+    let document_spec = DocumentSpec::from(render_spec);
+    document_spec.validate(&config)?;
+
+    // Given back once the rendering is done, or dropped.
+    let _worker = jobs.worker()?;
+
+    let timeout = config.render_timeout;
+    let logger = config.logger.clone();
+    let mut renderer = Renderer::new(config, Job::untracked(), document_spec)?;
+
+    let rendering = async {
+        let output_path = renderer.render_to_file().await?;
+        let filename = output_path
+            .file_name()
+            .map(|filename| filename.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let pdf = tokio::fs::read(output_path.to_owned())
+            .compat()
+            .await
+            .context("Error reading the generated PDF")
+            .map_err(|err| JobError {
+                code: ErrorCode::InternalError,
+                error: err.into(),
+            })?;
+
+        Ok::<_, JobError>((filename, pdf))
+    };
+
+    // Dropping the rendering on timeout kills LaTeX and deletes the workspace.
+    let (filename, pdf) = tokio::timer::Timeout::new(rendering.boxed().compat(), timeout)
+        .compat()
+        .await
+        .map_err(|err| match err.into_inner() {
+            Some(JobError {
+                code: ErrorCode::InternalError,
+                error,
+            }) => EndpointError::InternalServerError { cause: error },
+            Some(JobError {
+                code: ErrorCode::TimedOut,
+                error,
+            }) => EndpointError::GatewayTimeout { cause: error },
+            Some(JobError { error, .. }) => EndpointError::UnprocessableEntity { cause: error },
+            None => EndpointError::GatewayTimeout {
+                cause: format_err!("The rendering took more than {:?}.", timeout),
+            },
+        })?;
+
+    slog::debug!(logger, "Responding with {} ({} bytes).", filename, pdf.len());
+
+    let mut response = http::Response::new(pdf.into());
+    let headers = response.headers_mut();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        http::header::HeaderValue::from_static("application/pdf"),
+    );
+    headers.insert(
+        http::header::CONTENT_DISPOSITION,
+        http::header::HeaderValue::from_str(&crate::storage::content_disposition(&filename))
+            .map_err(failure::Error::from)?,
+    );
+
+    Ok(response)
+}
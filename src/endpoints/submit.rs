@@ -1,17 +1,17 @@
 use crate::papers::DocumentSpec;
 use crate::prelude::*;
-use crate::papers::Renderer;
-use futures::{FutureExt, TryFutureExt};
+use crate::papers::{JobSpec, Jobs};
+use serde_json::json;
 
-pub(crate) async fn submit(document_spec: DocumentSpec, config: Arc<Config>) -> Result<Response, EndpointError> {
+pub(crate) async fn submit(
+    document_spec: DocumentSpec,
+    idempotency_key: Option<String>,
+    config: Arc<Config>,
+    jobs: Jobs,
+) -> Result<Response, EndpointError> {
     document_spec.validate(&config)?;
 
-    tokio::executor::spawn(
-        Renderer::new(config, document_spec)?
-            .render()
-            .boxed()
-            .compat(),
-    );
+    let job_id = jobs.submit(JobSpec::Render(document_spec), idempotency_key)?;
 
-    Ok(empty_response())
+    Ok(json_response(&json!({ "job_id": job_id }))?)
 }
@@ -25,8 +25,10 @@ pub mod local_server;
 pub mod papers;
 /// Prelude.
 mod prelude;
+pub mod storage;
 /// Utility modules.
 pub mod utils;
 
 pub use app::app;
 pub use config::Config;
+pub use papers::Jobs;
@@ -1,4 +1,5 @@
 use crate::latex;
+use crate::papers::{Engine, EngineCommand, Engines};
 use crate::prelude::*;
 use crate::utils::templating::make_tera;
 use serde_json;
@@ -6,7 +7,7 @@ use serde_json::json;
 use std::fs::File;
 use std::io::prelude::*;
 
-fn render(document_spec: DocumentSpec) -> std::process::ExitStatus {
+fn render(document_spec: DocumentSpec, engine: &EngineCommand) -> std::process::ExitStatus {
     let DocumentSpec { variables, .. } = document_spec;
     let variables = latex::escape_tex(variables);
     let template_string = std::fs::File::open("template.tex.tera")
@@ -26,10 +27,8 @@ fn render(document_spec: DocumentSpec) -> std::process::ExitStatus {
     rendered_template_file
         .write_all(rendered_template.as_bytes())
         .unwrap();
-    let outcome = std::process::Command::new("xelatex")
-        .arg("-interaction=nonstopmode")
-        .arg("-file-line-error")
-        .arg("-shell-restricted")
+    let outcome = std::process::Command::new(&engine.program)
+        .args(&engine.args)
         .arg("rendered.tex")
         .output()
         .expect("latex error");
@@ -40,7 +39,12 @@ fn render(document_spec: DocumentSpec) -> std::process::ExitStatus {
 
 /// This function aims to make it simple to test a template locally: it serves the assets and the
 /// template from the local directory, and receives the PDF from the callback endpoint.
-pub fn render_locally() {
+///
+/// The engine is run like on the server, with the same `PAPERS_ENGINES` configuration.
+pub fn render_locally(engine: Option<Engine>) {
+    let engines = Engines::from_env().expect("invalid engines configuration");
+    let engine_command = engines.command(engine).expect("engine not allowed");
+
     let variables: serde_json::Value = if let Ok(file) = File::open("variables.json") {
         let bytes: Vec<u8> = file.bytes().collect::<Result<Vec<u8>, _>>().unwrap();
         serde_json::from_slice(&bytes).expect("variables.json is not valid JSON")
@@ -51,12 +55,18 @@ pub fn render_locally() {
     let document_spec = DocumentSpec {
         assets_urls: vec![],
         callback_url: PapersUri("unreachable".parse().unwrap()),
-        output_filename: "unreachable".to_string(),
+        output_filename: Some("unreachable".to_string()),
         template_url: PapersUri("unreachable".parse().unwrap()),
         variables,
         no_escape_tex: std::default::Default::default(),
+        engine,
+        metadata: None,
+        idempotency_key: None,
+        s3: Default::default(),
+        workspace: Default::default(),
+        url_expiration: None,
     };
 
-    let exit_status = render(document_spec);
+    let exit_status = render(document_spec, engine_command);
     std::process::exit(exit_status.code().unwrap_or(1));
 }
@@ -1,6 +1,7 @@
 #![deny(warnings)]
 
 use dotenv::dotenv;
+use futures01::{Future, IntoFuture};
 use std::sync::Arc;
 use structopt::StructOpt;
 
@@ -9,7 +10,21 @@ enum Command {
     #[structopt(name = "server", help = "Start the papers HTTP server")]
     Server,
     #[structopt(name = "local", help = "Produce PDF locally")]
-    Local,
+    Local {
+        #[structopt(
+            long = "engine",
+            help = "The LaTeX engine: xelatex, pdflatex, lualatex or tectonic"
+        )]
+        engine: Option<papers::papers::Engine>,
+    },
+    #[structopt(
+        name = "gc",
+        help = "Delete the stored files older than PAPERS_SUCCESS_RETENTION and PAPERS_FAILURE_RETENTION"
+    )]
+    Gc {
+        #[structopt(long = "dry-run", help = "Only list the files that would be deleted")]
+        dry_run: bool,
+    },
     #[structopt(name = "version", help = "Prints the current version of Papers")]
     Version,
     #[structopt(name = "help")]
@@ -40,9 +55,38 @@ fn main() -> Result<(), failure::Error> {
     let opts = Cli::from_args();
     match opts.command {
         Some(Command::Server) | None => {
-            warp::serve(papers::app(Arc::new(papers::Config::from_env()))).run(port)
+            let config = Arc::new(papers::Config::from_env()?);
+            let jobs = papers::Jobs::new(config.clone())?;
+            let logger = config.logger.clone();
+            let server = warp::serve(papers::app(config.clone(), jobs.clone()));
+
+            // The persisted jobs are spawned when resumed, so this has to happen on the runtime.
+            tokio::run(futures01::future::lazy(move || {
+                papers::storage::spawn_sweeper(config);
+
+                jobs.resume()
+                    .map_err(|err| {
+                        slog::error!(logger, "Could not resume the persisted jobs: {:?}.", err)
+                    })
+                    .into_future()
+                    .and_then(move |()| server.bind(port))
+            }))
+        }
+        Some(Command::Local { engine }) => papers::local_server::render_locally(engine),
+        Some(Command::Gc { dry_run }) => {
+            let config = Arc::new(papers::Config::from_env()?);
+            let sweep = papers::storage::collect_garbage(config, dry_run)?;
+
+            for key in &sweep.deleted {
+                println!("{}", key);
+            }
+            if sweep.errors > 0 {
+                return Err(failure::format_err!(
+                    "{} files could not be deleted",
+                    sweep.errors
+                ));
+            }
         }
-        Some(Command::Local) => papers::local_server::render_locally(),
         Some(Command::Version) => println!(env!("CARGO_PKG_VERSION")),
         Some(Command::Help) => Cli::clap().print_help().unwrap(),
     }
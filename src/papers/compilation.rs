@@ -0,0 +1,373 @@
+use crate::papers::{Engine, EngineCommand};
+use crate::prelude::*;
+use crate::utils::process::{output_with_limits, ResourceLimits};
+use crate::utils::sandbox::{self, Sandbox};
+use slog::{debug, warn, Logger};
+use std::path::Path;
+use std::process::Output;
+use std::time::Duration;
+
+/// What LaTeX and its packages write to the log when the document should be compiled again.
+const RERUN_MARKERS: &[&str] = &["Rerun to get", "Please rerun LaTeX", "Rerun LaTeX"];
+
+/// The extensions of the table of contents and of the lists of figures and tables, which LaTeX
+/// writes during a pass and reads during the next one.
+const LIST_EXTENSIONS: &[&str] = &["toc", "lof", "lot"];
+
+/// Compiles a template to PDF, running the engine as many times as needed for the references,
+/// the tables of contents and the page totals to settle, along with the bibliography and index
+/// tools the document uses.
+///
+/// The output of every program is kept in the workspace, in `papers-*.log` files. Each run is
+/// killed when it takes longer than the timeout, and runs in the sandbox when there is one.
+#[derive(Clone, Debug)]
+pub(crate) struct Compiler {
+    engine: EngineCommand,
+    max_passes: u32,
+    latexmk: bool,
+    timeout: Duration,
+    limits: ResourceLimits,
+    sandbox: Option<Sandbox>,
+}
+
+impl Compiler {
+    /// The compiler for `engine`, or the default engine. It fails when the engine is not
+    /// allowed.
+    pub(crate) fn new(config: &Config, engine: Option<Engine>) -> Result<Self, failure::Error> {
+        Ok(Compiler {
+            engine: config.engines.command(engine)?.clone(),
+            max_passes: config.latex_max_passes.max(1),
+            latexmk: config.latexmk,
+            timeout: config.latex_timeout,
+            limits: config.resource_limits,
+            sandbox: config.sandbox.clone(),
+        })
+    }
+
+    /// Compile `template`, which is in `dir`. The PDF is written next to it.
+    pub(crate) async fn compile(
+        &self,
+        logger: &Logger,
+        dir: &Path,
+        template: &Path,
+    ) -> Result<(), failure::Error> {
+        match self.engine.engine {
+            // Tectonic reruns itself and runs BibTeX on its own.
+            Engine::Tectonic => self.run_engine(logger, dir, template, 1).await,
+            _ if self.latexmk => self.run_latexmk(logger, dir, template).await,
+            _ => self.run_passes(logger, dir, template).await,
+        }
+    }
+
+    async fn run_passes(
+        &self,
+        logger: &Logger,
+        dir: &Path,
+        template: &Path,
+    ) -> Result<(), failure::Error> {
+        let aux_path = template.with_extension("aux");
+        let log_path = template.with_extension("log");
+        let mut previous_lists = read_lists(template);
+
+        for pass in 1..=self.max_passes {
+            self.run_engine(logger, dir, template, pass).await?;
+
+            // LaTeX checks the labels and the citations of the aux file itself, and asks for a
+            // rerun in the log. The tables of contents and the lists are only read on the next
+            // pass.
+            let log = std::fs::read(&log_path)
+                .map(|log| String::from_utf8_lossy(&log).into_owned())
+                .unwrap_or_default();
+            let lists = read_lists(template);
+            let mut rerun =
+                lists != previous_lists || RERUN_MARKERS.iter().any(|marker| log.contains(marker));
+
+            if pass == 1 {
+                let aux = std::fs::read(&aux_path).ok();
+                rerun |= self.run_tools(logger, dir, template, aux.as_ref()).await?;
+            }
+
+            if !rerun {
+                debug!(logger, "LaTeX output settled after {} passes.", pass);
+                return Ok(());
+            }
+
+            previous_lists = lists;
+        }
+
+        warn!(
+            logger,
+            "LaTeX output still changing after {} passes, keeping the last one.", self.max_passes
+        );
+
+        Ok(())
+    }
+
+    /// Run the bibliography and index tools the document needs, after the first pass. Returns
+    /// whether any did run.
+    async fn run_tools(
+        &self,
+        logger: &Logger,
+        dir: &Path,
+        template: &Path,
+        aux: Option<&Vec<u8>>,
+    ) -> Result<bool, failure::Error> {
+        let stem = template
+            .file_stem()
+            .ok_or_else(|| format_err!("Invalid template path {:?}", template))?;
+        let mut ran = false;
+
+        // biblatex writes a control file for biber, while plain BibTeX reads the aux file.
+        if template.with_extension("bcf").exists() {
+            self.run_tool(logger, dir, "biber", &[stem]).await?;
+            ran = true;
+        } else if aux.filter(|aux| contains(aux, b"\\bibdata")).is_some() {
+            self.run_tool(logger, dir, "bibtex", &[stem]).await?;
+            ran = true;
+        }
+
+        if template.with_extension("idx").exists() {
+            let idx = template.with_extension("idx");
+            let idx = idx.file_name().expect("idx file has a name");
+            self.run_tool(logger, dir, "makeindex", &[idx]).await?;
+            ran = true;
+        }
+
+        Ok(ran)
+    }
+
+    async fn run_engine(
+        &self,
+        logger: &Logger,
+        dir: &Path,
+        template: &Path,
+        pass: u32,
+    ) -> Result<(), failure::Error> {
+        let name = self.engine.engine.name();
+        debug!(logger, "Spawning {} (pass {}).", name, pass);
+
+        let mut command = sandbox::command(self.sandbox.as_ref(), &self.engine.program, dir);
+        command.args(&self.engine.args).arg(template);
+        let output = output_with_limits(command, name, self.timeout, self.limits).await?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        keep_output(dir, &format!("pass-{}-{}", pass, name), &output)?;
+
+        if !output.status.success() {
+            return Err(format_err!("LaTeX failed. Stdout:\n{}", stdout));
+        }
+
+        debug!(logger, "LaTeX succeeded. Stdout:\n{}", stdout);
+
+        Ok(())
+    }
+
+    /// Let latexmk run the engine and the tools, with the configured engine command.
+    async fn run_latexmk(
+        &self,
+        logger: &Logger,
+        dir: &Path,
+        template: &Path,
+    ) -> Result<(), failure::Error> {
+        // latexmk runs the command with the shell, so the words are quoted.
+        let mut engine = String::from("-pdflatex=");
+        engine.push_str(&shell_quote(&self.engine.program.to_string_lossy()));
+        for arg in &self.engine.args {
+            engine.push(' ');
+            engine.push_str(&shell_quote(arg));
+        }
+        engine.push_str(" %O %S");
+        let max_repeat = format!("$max_repeat={}", self.max_passes);
+
+        debug!(
+            logger,
+            "Spawning latexmk with {}.",
+            self.engine.engine.name()
+        );
+
+        let mut command = sandbox::command(self.sandbox.as_ref(), "latexmk", dir);
+        command
+            .arg("-pdf")
+            .arg(&engine)
+            .arg("-e")
+            .arg(&max_repeat)
+            .arg(template);
+        // latexmk runs all the passes, so it gets the time of all of them.
+        let timeout = self.timeout * self.max_passes;
+        let output = output_with_limits(command, "latexmk", timeout, self.limits).await?;
+
+        keep_output(dir, "latexmk", &output)?;
+
+        if !output.status.success() {
+            return Err(format_err!(
+                "LaTeX failed. latexmk output:\n{}",
+                crate::utils::process::whole_output(&output)?
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Run a bibliography or index tool in `dir`.
+    async fn run_tool(
+        &self,
+        logger: &Logger,
+        dir: &Path,
+        program: &str,
+        args: &[&std::ffi::OsStr],
+    ) -> Result<(), failure::Error> {
+        debug!(logger, "Spawning {}.", program);
+
+        let mut command = sandbox::command(self.sandbox.as_ref(), program, dir);
+        command.args(args);
+        let output = output_with_limits(command, program, self.timeout, self.limits).await?;
+
+        keep_output(dir, program, &output)?;
+
+        if !output.status.success() {
+            return Err(format_err!(
+                "{} failed. Output:\n{}",
+                program,
+                crate::utils::process::whole_output(&output)?
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The contents of the table of contents and of the lists of `template`. Missing and empty
+/// files are the same, since both make empty lists.
+fn read_lists(template: &Path) -> Vec<Option<Vec<u8>>> {
+    LIST_EXTENSIONS
+        .iter()
+        .map(|extension| {
+            std::fs::read(template.with_extension(extension))
+                .ok()
+                .filter(|contents| !contents.is_empty())
+        })
+        .collect()
+}
+
+/// Quote `word` for the shell latexmk runs the engine with.
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+/// Write the stdout and stderr of a program to `papers-<name>.log` in the workspace.
+fn keep_output(dir: &Path, name: &str, output: &Output) -> Result<(), failure::Error> {
+    let mut contents = output.stdout.clone();
+    contents.extend_from_slice(&output.stderr);
+    std::fs::write(dir.join(format!("papers-{}.log", name)), contents)?;
+
+    Ok(())
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{FutureExt, TryFutureExt};
+
+    #[test]
+    fn contains_finds_the_bibliography() {
+        assert!(contains(
+            b"\\relax\n\\bibstyle{plain}\n\\bibdata{refs}\n",
+            b"\\bibdata"
+        ));
+        assert!(!contains(b"\\relax\n", b"\\bibdata"));
+    }
+
+    /// Run a fake engine, the shell `script`, with up to `max_passes`, and return how many
+    /// passes ran.
+    fn count_passes(script: &str, max_passes: u32) -> (mktemp::Temp, u32) {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let template = dir.as_ref().join("doc.tex");
+        std::fs::write(&template, "").unwrap();
+        let logger = Logger::root(slog::Discard, slog::o!());
+        // The script knows the number of the pass as $n.
+        let script = format!(
+            "n=$(($(cat doc.count 2>/dev/null || echo 0) + 1)); echo $n > doc.count; {}",
+            script
+        );
+        let compiler = Compiler {
+            engine: EngineCommand {
+                engine: Engine::Xelatex,
+                program: "sh".into(),
+                args: vec!["-c".to_owned(), script, "sh".to_owned()],
+            },
+            max_passes,
+            latexmk: false,
+            timeout: Duration::from_secs(10),
+            limits: ResourceLimits::default(),
+            sandbox: None,
+        };
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(
+                compiler
+                    .compile(&logger, dir.as_ref(), &template)
+                    .boxed_local()
+                    .compat(),
+            )
+            .unwrap();
+
+        let count = std::fs::read_to_string(dir.as_ref().join("doc.count")).unwrap();
+        (dir, count.trim().parse().unwrap())
+    }
+
+    #[test]
+    fn passes_run_until_the_table_of_contents_settles() {
+        // The table of contents changes up to the third pass.
+        let script = "if [ $n -le 3 ]; then echo $n > doc.toc; fi";
+
+        let (dir, passes) = count_passes(script, 5);
+        assert_eq!(passes, 4);
+        assert!(dir.as_ref().join("papers-pass-2-xelatex.log").exists());
+        assert_eq!(count_passes(script, 2).1, 2);
+    }
+
+    #[test]
+    fn passes_run_while_latex_asks_for_it() {
+        let script = "echo $n > doc.aux; if [ $n -le 1 ]; then \
+                      echo 'LaTeX Warning: Label(s) may have changed. \
+                      Rerun to get cross-references right.' > doc.log; \
+                      else echo > doc.log; fi";
+
+        assert_eq!(count_passes(script, 5).1, 2);
+    }
+
+    #[test]
+    fn documents_without_references_are_compiled_once() {
+        // The aux file is new, but LaTeX does not ask for a rerun.
+        assert_eq!(
+            count_passes("echo '\\relax' > doc.aux; : > doc.toc", 5).1,
+            1
+        );
+    }
+
+    #[test]
+    fn engine_words_are_quoted_for_latexmk() {
+        assert_eq!(
+            shell_quote("/opt/TeX Live/xelatex"),
+            "'/opt/TeX Live/xelatex'"
+        );
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn compiler_uses_the_configured_passes() {
+        let config = Config::for_tests();
+        let compiler = Compiler::new(&config, None).unwrap();
+
+        assert_eq!(compiler.engine.engine, Engine::Xelatex);
+        assert_eq!(compiler.max_passes, config.latex_max_passes);
+        assert!(Compiler::new(&config, Some(Engine::Tectonic)).is_err());
+    }
+}
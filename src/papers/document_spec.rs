@@ -1,6 +1,8 @@
 use crate::latex::escape_tex;
 use crate::papers::uri::PapersUri;
+use crate::papers::{Engine, Metadata, WorkspaceOptions};
 use crate::prelude::*;
+use crate::storage::ObjectSettings;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -9,18 +11,37 @@ use slog::error;
 /// See https://serde.rs/custom-date-format.html for the custom deserialization.
 /// An alternative design would be making a newtype containing an Uri and implementing Deserialize
 /// for that.
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct DocumentSpec {
     #[serde(default = "default_assets")]
     pub assets_urls: Vec<PapersUri>,
     pub callback_url: PapersUri,
-    #[serde(default = "default_output_filename")]
-    pub output_filename: String,
+    /// See [`output_filename()`](DocumentSpec::output_filename).
+    #[serde(default)]
+    pub output_filename: Option<String>,
     pub template_url: PapersUri,
     #[serde(default = "default_value")]
     pub variables: serde_json::Value,
     #[serde(default = "return_false")]
     pub no_escape_tex: bool,
+    /// The LaTeX engine, or the default one of the configuration.
+    #[serde(default)]
+    pub engine: Option<Engine>,
+    /// Echoed in the summary of the job.
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+    /// Makes retried submissions return the original job.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// The storage class and tags of the uploaded objects.
+    #[serde(default)]
+    pub s3: ObjectSettings,
+    /// When and how to upload the workspace.
+    #[serde(default)]
+    pub workspace: WorkspaceOptions,
+    /// See [`url_expiration()`](DocumentSpec::url_expiration).
+    #[serde(default)]
+    pub url_expiration: Option<u64>,
 }
 
 impl DocumentSpec {
@@ -40,6 +61,17 @@ impl DocumentSpec {
             });
         }
 
+        if let Err(cause) = config.engines.command(self.engine) {
+            return Err(EndpointError::UnprocessableEntity { cause });
+        }
+
+        // The URL would already be expired when the callback gets it.
+        if self.url_expiration == Some(0) {
+            return Err(EndpointError::UnprocessableEntity {
+                cause: format_err!("url_expiration should be at least 1 second."),
+            });
+        }
+
         Ok(())
     }
 
@@ -55,9 +87,59 @@ impl DocumentSpec {
         self.callback_url.0.to_string()
     }
 
+    /// The requested name of the PDF, or `out_<current time>.pdf`.
+    pub fn output_filename(&self) -> String {
+        self.output_filename
+            .clone()
+            .unwrap_or_else(default_output_filename)
+    }
+
     pub fn asset_urls(&self) -> impl std::iter::Iterator<Item = &hyper::Uri> {
         self.assets_urls.iter().map(|uri| &uri.0)
     }
+
+    /// How long the URL to the PDF should be valid, if the request asks for a shorter validity
+    /// than the default.
+    pub fn url_expiration(&self) -> Option<std::time::Duration> {
+        self.url_expiration.map(std::time::Duration::from_secs)
+    }
+}
+
+/// The body of `POST /render`. It is a [`DocumentSpec`](DocumentSpec) without a callback URL,
+/// since the PDF is returned in the response.
+#[derive(Deserialize, Debug)]
+pub struct RenderSpec {
+    #[serde(default = "default_assets")]
+    pub assets_urls: Vec<PapersUri>,
+    #[serde(default)]
+    pub output_filename: Option<String>,
+    pub template_url: PapersUri,
+    #[serde(default = "default_value")]
+    pub variables: serde_json::Value,
+    #[serde(default = "return_false")]
+    pub no_escape_tex: bool,
+    #[serde(default)]
+    pub engine: Option<Engine>,
+}
+
+impl From<RenderSpec> for DocumentSpec {
+    fn from(render_spec: RenderSpec) -> DocumentSpec {
+        DocumentSpec {
+            assets_urls: render_spec.assets_urls,
+            // Synchronous renders never report to a callback URL.
+            callback_url: PapersUri("unreachable".parse().unwrap()),
+            output_filename: render_spec.output_filename,
+            template_url: render_spec.template_url,
+            variables: render_spec.variables,
+            no_escape_tex: render_spec.no_escape_tex,
+            engine: render_spec.engine,
+            metadata: None,
+            idempotency_key: None,
+            s3: ObjectSettings::default(),
+            workspace: WorkspaceOptions::default(),
+            url_expiration: None,
+        }
+    }
 }
 
 fn return_false() -> bool {
@@ -79,6 +161,8 @@ fn default_value() -> serde_json::Value {
 #[cfg(test)]
 mod tests {
     use super::DocumentSpec;
+    use crate::papers::Engine;
+    use crate::Config;
     use serde_json::{from_str, json};
 
     #[test]
@@ -101,6 +185,41 @@ mod tests {
         assert_eq!(spec.assets_urls.len(), 0);
     }
 
+    #[test]
+    fn it_validates_the_engine() {
+        let json = r#"{
+            "callback_url": "abc",
+            "template_url": "def",
+            "engine": "lualatex"
+        }"#;
+        let spec = from_str::<DocumentSpec>(&json).unwrap();
+        assert_eq!(spec.engine, Some(Engine::Lualatex));
+        assert!(spec.validate(&Config::for_tests()).is_err());
+
+        let spec = DocumentSpec {
+            engine: None,
+            ..spec
+        };
+        assert!(spec.validate(&Config::for_tests()).is_ok());
+    }
+
+    #[test]
+    fn it_validates_the_url_expiration() {
+        let json = r#"{
+            "callback_url": "abc",
+            "template_url": "def",
+            "url_expiration": 0
+        }"#;
+        let spec = from_str::<DocumentSpec>(&json).unwrap();
+        assert!(spec.validate(&Config::for_tests()).is_err());
+
+        let spec = DocumentSpec {
+            url_expiration: Some(1),
+            ..spec
+        };
+        assert!(spec.validate(&Config::for_tests()).is_ok());
+    }
+
     #[test]
     fn it_parses_uris() {
         let json = r#"{
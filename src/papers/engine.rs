@@ -0,0 +1,190 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The programs that compile the LaTeX templates to PDF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// XeLaTeX, the default, with Unicode and system fonts.
+    Xelatex,
+    /// pdfLaTeX, for the templates relying on `inputenc` and Type 1 fonts.
+    Pdflatex,
+    /// LuaLaTeX, for OpenType features.
+    Lualatex,
+    /// Tectonic, which fetches the packages it needs.
+    Tectonic,
+}
+
+impl Engine {
+    /// The name of the engine, which is also the name of its binary.
+    pub fn name(self) -> &'static str {
+        match self {
+            Engine::Xelatex => "xelatex",
+            Engine::Pdflatex => "pdflatex",
+            Engine::Lualatex => "lualatex",
+            Engine::Tectonic => "tectonic",
+        }
+    }
+
+    /// The arguments given before the path of the template, unless configured otherwise.
+    fn default_args(self) -> Vec<String> {
+        let args: &[&str] = match self {
+            Engine::Xelatex | Engine::Pdflatex | Engine::Lualatex => &[
+                "-interaction=nonstopmode",
+                "-file-line-error",
+                "-shell-restricted",
+            ],
+            Engine::Tectonic => &["--keep-logs"],
+        };
+
+        args.iter().map(|arg| (*arg).to_owned()).collect()
+    }
+}
+
+impl std::str::FromStr for Engine {
+    type Err = failure::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src.trim() {
+            "xelatex" => Ok(Engine::Xelatex),
+            "pdflatex" => Ok(Engine::Pdflatex),
+            "lualatex" => Ok(Engine::Lualatex),
+            "tectonic" => Ok(Engine::Tectonic),
+            other => Err(format_err!(
+                "Unknown engine {:?}, expected xelatex, pdflatex, lualatex or tectonic",
+                other
+            )),
+        }
+    }
+}
+
+/// How an engine is run. The path of the template is added after the arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineCommand {
+    /// The engine.
+    pub engine: Engine,
+    /// The binary, looked up in the `PATH` when it is not a path.
+    pub program: PathBuf,
+    /// The arguments before the path of the template.
+    pub args: Vec<String>,
+}
+
+impl EngineCommand {
+    fn new(engine: Engine) -> Self {
+        EngineCommand {
+            engine,
+            program: PathBuf::from(engine.name()),
+            args: engine.default_args(),
+        }
+    }
+}
+
+/// The engines the operator allows, and the one used when a request does not choose.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Engines {
+    default: Engine,
+    commands: BTreeMap<Engine, EngineCommand>,
+}
+
+impl Engines {
+    /// Read `PAPERS_ENGINES` and `PAPERS_DEFAULT_ENGINE`, which defaults to the first allowed
+    /// engine, and the binary and arguments of each allowed engine from `PAPERS_<ENGINE>_PATH`
+    /// and `PAPERS_<ENGINE>_ARGS`.
+    pub fn from_env() -> Result<Self, failure::Error> {
+        let allowed = std::env::var("PAPERS_ENGINES").unwrap_or_else(|_| "xelatex".to_owned());
+        let mut commands = BTreeMap::new();
+        let mut first = None;
+
+        for engine in allowed.split(',').filter(|name| !name.trim().is_empty()) {
+            let engine: Engine = engine.parse()?;
+            let mut command = EngineCommand::new(engine);
+            let prefix = format!("PAPERS_{}", engine.name().to_uppercase());
+
+            if let Ok(program) = std::env::var(format!("{}_PATH", prefix)) {
+                command.program = PathBuf::from(program);
+            }
+            if let Ok(args) = std::env::var(format!("{}_ARGS", prefix)) {
+                command.args = args.split_whitespace().map(str::to_owned).collect();
+            }
+
+            commands.insert(engine, command);
+            first = first.or(Some(engine));
+        }
+
+        let default = match std::env::var("PAPERS_DEFAULT_ENGINE") {
+            Ok(engine) => engine.parse()?,
+            Err(_) => {
+                first.ok_or_else(|| format_err!("PAPERS_ENGINES does not allow any engine"))?
+            }
+        };
+
+        if !commands.contains_key(&default) {
+            return Err(format_err!(
+                "The default engine {} is not allowed by PAPERS_ENGINES",
+                default.name()
+            ));
+        }
+
+        Ok(Engines { default, commands })
+    }
+
+    /// The command running `engine`, or the default engine. It fails when the engine is not
+    /// allowed.
+    pub fn command(&self, engine: Option<Engine>) -> Result<&EngineCommand, failure::Error> {
+        let engine = engine.unwrap_or(self.default);
+
+        self.commands.get(&engine).ok_or_else(|| {
+            format_err!(
+                "The {} engine is not allowed. Allowed engines: {}",
+                engine.name(),
+                self.commands
+                    .keys()
+                    .map(|engine| engine.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+    }
+}
+
+impl Default for Engines {
+    /// Only XeLaTeX, with the default arguments.
+    fn default() -> Self {
+        let mut commands = BTreeMap::new();
+        commands.insert(Engine::Xelatex, EngineCommand::new(Engine::Xelatex));
+
+        Engines {
+            default: Engine::Xelatex,
+            commands,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_allowed_engines_have_a_command() {
+        let engines = Engines::default();
+
+        let xelatex = engines.command(None).unwrap();
+        assert_eq!(xelatex.program, PathBuf::from("xelatex"));
+        assert!(xelatex.args.contains(&"-shell-restricted".to_owned()));
+
+        assert_eq!(engines.command(Some(Engine::Xelatex)).unwrap(), xelatex);
+        assert!(engines.command(Some(Engine::Lualatex)).is_err());
+    }
+
+    #[test]
+    fn engines_are_parsed() {
+        assert_eq!("pdflatex".parse::<Engine>().unwrap(), Engine::Pdflatex);
+        assert!("latex".parse::<Engine>().is_err());
+        assert_eq!(
+            serde_json::from_str::<Engine>("\"tectonic\"").unwrap(),
+            Engine::Tectonic
+        );
+    }
+}
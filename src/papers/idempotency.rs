@@ -0,0 +1,152 @@
+use crate::papers::JobId;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+/// What the previous submissions with an idempotency key say about a new one.
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    /// The key was not used within the window.
+    New,
+    /// The same request was already accepted as this job.
+    Existing(JobId),
+    /// The same request is still being accepted.
+    Pending,
+    /// The key was used for a different request.
+    Conflict,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// `None` while the submission is being accepted.
+    job_id: Option<JobId>,
+    fingerprint: String,
+    created_at: DateTime<Utc>,
+}
+
+/// Remembers the idempotency keys of the accepted submissions for a limited time.
+#[derive(Debug)]
+pub struct IdempotencyKeys {
+    window: Duration,
+    entries: HashMap<String, Entry>,
+}
+
+impl IdempotencyKeys {
+    pub fn new(window: Duration) -> Self {
+        IdempotencyKeys {
+            window,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Check a submission with `key` and a body identified by `fingerprint`. Expired keys are
+    /// forgotten first.
+    ///
+    /// A new key is reserved, so the submission can be accepted without holding the lock of the
+    /// keys. It must then be [inserted](IdempotencyKeys::insert) or
+    /// [released](IdempotencyKeys::release).
+    pub fn lookup(&mut self, key: &str, fingerprint: &str, now: DateTime<Utc>) -> Lookup {
+        let window = self.window;
+        self.entries
+            .retain(|_, entry| now.signed_duration_since(entry.created_at) < window);
+
+        match self.entries.get(key) {
+            None => {
+                self.entries.insert(
+                    key.to_owned(),
+                    Entry {
+                        job_id: None,
+                        fingerprint: fingerprint.to_owned(),
+                        created_at: now,
+                    },
+                );
+                Lookup::New
+            }
+            Some(entry) if entry.fingerprint != fingerprint => Lookup::Conflict,
+            Some(Entry {
+                job_id: Some(job_id),
+                ..
+            }) => Lookup::Existing(*job_id),
+            Some(_) => Lookup::Pending,
+        }
+    }
+
+    /// Forget a key reserved by [`lookup`](IdempotencyKeys::lookup) whose submission was not
+    /// accepted, so it can be retried.
+    pub fn release(&mut self, key: &str) {
+        if let Some(Entry { job_id: None, .. }) = self.entries.get(key) {
+            self.entries.remove(key);
+        }
+    }
+
+    /// Remember that the submission with `key` was accepted as `job_id`.
+    pub fn insert(
+        &mut self,
+        key: String,
+        fingerprint: String,
+        job_id: JobId,
+        created_at: DateTime<Utc>,
+    ) {
+        self.entries.insert(
+            key,
+            Entry {
+                job_id: Some(job_id),
+                fingerprint,
+                created_at,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job_id() -> JobId {
+        "7f9e8c4a-3b1d-4e5f-9a2b-6c8d0e1f2a3b".parse().unwrap()
+    }
+
+    #[test]
+    fn repeated_submissions_return_the_original_job() {
+        let mut keys = IdempotencyKeys::new(Duration::hours(1));
+        let now = Utc::now();
+
+        assert_eq!(keys.lookup("key", "body", now), Lookup::New);
+        keys.insert("key".to_owned(), "body".to_owned(), job_id(), now);
+
+        assert_eq!(keys.lookup("key", "body", now), Lookup::Existing(job_id()));
+        assert_eq!(keys.lookup("key", "other body", now), Lookup::Conflict);
+        assert_eq!(keys.lookup("other key", "body", now), Lookup::New);
+    }
+
+    #[test]
+    fn keys_are_reserved_until_the_submission_is_accepted() {
+        let mut keys = IdempotencyKeys::new(Duration::hours(1));
+        let now = Utc::now();
+
+        assert_eq!(keys.lookup("key", "body", now), Lookup::New);
+        assert_eq!(keys.lookup("key", "body", now), Lookup::Pending);
+        assert_eq!(keys.lookup("key", "other body", now), Lookup::Conflict);
+
+        keys.release("key");
+        assert_eq!(keys.lookup("key", "body", now), Lookup::New);
+
+        keys.insert("key".to_owned(), "body".to_owned(), job_id(), now);
+        keys.release("key");
+        assert_eq!(keys.lookup("key", "body", now), Lookup::Existing(job_id()));
+    }
+
+    #[test]
+    fn keys_expire_after_the_window() {
+        let mut keys = IdempotencyKeys::new(Duration::hours(1));
+        let now = Utc::now();
+
+        keys.insert(
+            "key".to_owned(),
+            "body".to_owned(),
+            job_id(),
+            now - Duration::hours(2),
+        );
+
+        assert_eq!(keys.lookup("key", "other body", now), Lookup::New);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::papers::{JobId, JobSpec, JobStatus};
+use crate::prelude::*;
+use slog::{warn, Logger};
+use std::path::{Path, PathBuf};
+
+const SPEC_FILE_NAME: &str = "spec.json";
+const STATUS_FILE_NAME: &str = "status.json";
+
+/// Persists jobs on the local filesystem so they survive a restart.
+///
+/// Every job gets a directory named after its id under `<data dir>/jobs`, containing the spec it
+/// was submitted with and its latest status. Files are replaced atomically, so a crash in the
+/// middle of a write leaves the previous version in place.
+#[derive(Debug)]
+pub struct JobStore {
+    jobs_dir: PathBuf,
+}
+
+impl JobStore {
+    /// Open the store in `data_dir`, creating the directory if needed.
+    pub fn open(data_dir: &Path) -> Result<Self, failure::Error> {
+        let jobs_dir = data_dir.join("jobs");
+
+        std::fs::create_dir_all(&jobs_dir)
+            .with_context(|_| format!("Could not create the jobs directory {:?}", jobs_dir))?;
+
+        Ok(JobStore { jobs_dir })
+    }
+
+    fn job_dir(&self, id: &JobId) -> PathBuf {
+        self.jobs_dir.join(id.to_string())
+    }
+
+    /// Persist a newly accepted job.
+    pub fn insert(&self, status: &JobStatus, spec: &JobSpec) -> Result<(), failure::Error> {
+        let job_dir = self.job_dir(&status.id);
+
+        std::fs::create_dir_all(&job_dir)?;
+        write_json_atomically(&job_dir.join(SPEC_FILE_NAME), spec)?;
+        write_json_atomically(&job_dir.join(STATUS_FILE_NAME), status)
+    }
+
+    /// Record a state transition.
+    pub fn update(&self, status: &JobStatus) -> Result<(), failure::Error> {
+        write_json_atomically(&self.job_dir(&status.id).join(STATUS_FILE_NAME), status)
+    }
+
+    /// Forget about a job.
+    pub fn remove(&self, id: &JobId) -> Result<(), failure::Error> {
+        std::fs::remove_dir_all(self.job_dir(id))?;
+        Ok(())
+    }
+
+    /// Load all the persisted jobs. Jobs that cannot be read are logged and skipped.
+    pub fn load_all(&self, logger: &Logger) -> Result<Vec<(JobStatus, JobSpec)>, failure::Error> {
+        let mut jobs = Vec::new();
+
+        for entry in std::fs::read_dir(&self.jobs_dir)? {
+            let job_dir = entry?.path();
+
+            match load_job(&job_dir) {
+                Ok(job) => jobs.push(job),
+                Err(err) => warn!(logger, "Skipping unreadable job in {:?}: {:?}.", job_dir, err),
+            }
+        }
+
+        Ok(jobs)
+    }
+}
+
+fn load_job(job_dir: &Path) -> Result<(JobStatus, JobSpec), failure::Error> {
+    let status = std::fs::read(job_dir.join(STATUS_FILE_NAME))?;
+    let spec = std::fs::read(job_dir.join(SPEC_FILE_NAME))?;
+
+    Ok((serde_json::from_slice(&status)?, serde_json::from_slice(&spec)?))
+}
+
+/// Write to a temporary file next to `path`, then rename it over `path`.
+///
+/// Every write gets its own temporary file, so concurrent writes cannot interleave. The file and
+/// the directory are synced, so the new version survives a crash once this returns.
+fn write_json_atomically<T: serde::Serialize>(
+    path: &Path,
+    value: &T,
+) -> Result<(), failure::Error> {
+    use std::io::Write;
+
+    let dir = path
+        .parent()
+        .ok_or_else(|| format_err!("{:?} is not in a directory", path))?;
+    let temp = mktemp::Temp::new_file_in(dir)?;
+
+    let mut file = std::fs::OpenOptions::new().write(true).open(&temp)?;
+    file.write_all(&serde_json::to_vec(value)?)?;
+    file.sync_all()?;
+
+    std::fs::rename(&temp, path).with_context(|_| format!("Could not write {:?}", path))?;
+    // The file was renamed, there is nothing left to delete.
+    temp.release();
+    std::fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::papers::{JobState, Jobs};
+    use serde_json::json;
+
+    fn document_spec() -> JobSpec {
+        JobSpec::Render(
+            serde_json::from_value(json!({
+                "callback_url": "http://example.com/callback",
+                "template_url": "http://example.com/template",
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn jobs_roundtrip_through_the_store() {
+        let data_dir = mktemp::Temp::new_dir().unwrap();
+        let store = JobStore::open(data_dir.as_ref()).unwrap();
+        let jobs = Jobs::new(Arc::new(Config::for_tests())).unwrap();
+        let job = jobs.create(&document_spec()).unwrap();
+        let mut status = jobs.get(&job.id()).unwrap();
+
+        store.insert(&status, &document_spec()).unwrap();
+        status.state = JobState::Compiling;
+        store.update(&status).unwrap();
+
+        let loaded = store.load_all(&Config::for_tests().logger).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0.id, job.id());
+        assert_eq!(loaded[0].0.state, JobState::Compiling);
+
+        store.remove(&job.id()).unwrap();
+        assert!(store
+            .load_all(&Config::for_tests().logger)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn writes_leave_no_temporary_files_behind() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let path = dir.as_ref().join(STATUS_FILE_NAME);
+
+        write_json_atomically(&path, &json!({ "state": "queued" })).unwrap();
+        write_json_atomically(&path, &json!({ "state": "compiling" })).unwrap();
+
+        let contents: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(contents, json!({ "state": "compiling" }));
+        assert_eq!(std::fs::read_dir(dir.as_ref()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn unreadable_jobs_are_skipped() {
+        let data_dir = mktemp::Temp::new_dir().unwrap();
+        let store = JobStore::open(data_dir.as_ref()).unwrap();
+
+        std::fs::create_dir_all(data_dir.as_ref().join("jobs").join("garbage")).unwrap();
+
+        assert!(store
+            .load_all(&Config::for_tests().logger)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,1133 @@
+use crate::papers::idempotency::{IdempotencyKeys, Lookup};
+use crate::papers::job_store::JobStore;
+use crate::papers::queue::{Push, Queue};
+use crate::papers::{ErrorCode, Merger, Metadata, Outcome, Renderer, Summary};
+use crate::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::{abortable, AbortHandle};
+use futures::{FutureExt, TryFutureExt};
+use serde::{Deserialize, Serialize};
+use slog::{error, info, o, warn, Logger};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+/// How long the status of a finished job is kept around for `GET /jobs/:id`.
+const FINISHED_JOBS_RETENTION_HOURS: i64 = 24;
+
+/// How many times a job is started over after papers was interrupted while processing it.
+const MAX_RESTARTS: u32 = 2;
+
+/// The delay clients are asked to wait before retrying when the queue is full, in seconds.
+const QUEUE_FULL_RETRY_AFTER: u32 = 30;
+
+/// The work to be done for a job, typically a [`Renderer`](crate::papers::Renderer) or a
+/// [`Merger`](crate::papers::Merger) future.
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The unique identifier of a job, returned by the `/submit` and `/merge` endpoints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct JobId(uuid::Uuid);
+
+impl JobId {
+    fn new() -> Self {
+        JobId(uuid::Uuid::new_v4())
+    }
+}
+
+impl std::fmt::Display for JobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::str::FromStr for JobId {
+    type Err = uuid::parser::ParseError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        src.parse().map(JobId)
+    }
+}
+
+/// The lifecycle of a job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Downloading,
+    Compiling,
+    Uploading,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    /// Whether the job is done, successfully or not. A finished job does not change state
+    /// anymore.
+    pub fn is_finished(self) -> bool {
+        self == JobState::Succeeded || self == JobState::Failed || self == JobState::Cancelled
+    }
+}
+
+/// What `GET /jobs/:id` returns.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JobStatus {
+    pub id: JobId,
+    pub state: JobState,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// How many times the job was started over because papers was interrupted while processing
+    /// it.
+    pub restarts: u32,
+    /// The `metadata` the job was submitted with.
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+    /// The summary sent to the callback URL, once the job is finished.
+    pub summary: Option<Summary>,
+}
+
+/// What a job was submitted with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", content = "spec", rename_all = "lowercase")]
+pub enum JobSpec {
+    /// A `POST /submit`.
+    Render(DocumentSpec),
+    /// A `POST /merge`.
+    Merge(MergeSpec),
+}
+
+impl JobSpec {
+    fn callback_url(&self) -> String {
+        match self {
+            JobSpec::Render(document_spec) => document_spec.callback_url(),
+            JobSpec::Merge(merge_spec) => merge_spec.callback_url(),
+        }
+    }
+
+    fn metadata(&self) -> Option<Metadata> {
+        match self {
+            JobSpec::Render(document_spec) => document_spec.metadata.clone(),
+            JobSpec::Merge(merge_spec) => merge_spec.metadata.clone(),
+        }
+    }
+
+    fn idempotency_key(&self) -> Option<String> {
+        match self {
+            JobSpec::Render(document_spec) => document_spec.idempotency_key.clone(),
+            JobSpec::Merge(merge_spec) => merge_spec.idempotency_key.clone(),
+        }
+    }
+
+    fn set_idempotency_key(&mut self, key: Option<String>) {
+        match self {
+            JobSpec::Render(document_spec) => document_spec.idempotency_key = key,
+            JobSpec::Merge(merge_spec) => merge_spec.idempotency_key = key,
+        }
+    }
+
+    /// A hash of the spec without its idempotency key, to tell whether two submissions with the
+    /// same key are the same request.
+    fn fingerprint(&self) -> Result<String, failure::Error> {
+        use sha2::{Digest, Sha256};
+
+        let mut spec = self.clone();
+        spec.set_idempotency_key(None);
+
+        Ok(hex::encode(Sha256::digest(&serde_json::to_vec(&spec)?)))
+    }
+
+    /// Build the future doing the actual work for the job.
+    fn into_task(self, config: Arc<Config>, job: Job) -> Result<Task, failure::Error> {
+        let task = match self {
+            JobSpec::Render(document_spec) => Renderer::new(config, job, document_spec)?
+                .render()
+                .map(|_| ())
+                .boxed(),
+            JobSpec::Merge(merge_spec) => Merger::new(config, job, merge_spec)?
+                .merge_documents()
+                .map(|_| ())
+                .boxed(),
+        };
+
+        Ok(task)
+    }
+}
+
+/// The registry of the jobs known to this papers instance, and the queue they wait in before one
+/// of the `max_concurrent_jobs` workers picks them up. Cloning it is cheap and yields a handle to
+/// the same registry.
+///
+/// When `PAPERS_DATA_DIR` is set, jobs are also persisted in a [`JobStore`](JobStore), and
+/// [`resume`](Jobs::resume) picks up the jobs a previous run of papers did not finish.
+#[derive(Clone)]
+pub struct Jobs {
+    config: Arc<Config>,
+    statuses: Arc<Mutex<HashMap<JobId, JobStatus>>>,
+    queue: Arc<Mutex<Queue<JobId, Task>>>,
+    /// The handles to cancel the jobs that are queued or running.
+    cancellations: Arc<Mutex<HashMap<JobId, Cancellation>>>,
+    /// The idempotency keys of the jobs submitted within `config.idempotency_window`.
+    idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+    store: Option<Arc<JobStore>>,
+}
+
+/// What it takes to cancel a job.
+struct Cancellation {
+    abort_handle: AbortHandle,
+    callback_url: String,
+}
+
+impl std::fmt::Debug for Jobs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Jobs")
+            .field("statuses", &self.statuses)
+            .field("store", &self.store)
+            .finish()
+    }
+}
+
+impl Jobs {
+    /// Create an empty registry, with the queue limits and the data directory from `config`.
+    pub fn new(config: Arc<Config>) -> Result<Self, failure::Error> {
+        let store = match &config.data_dir {
+            Some(data_dir) => Some(Arc::new(JobStore::open(data_dir)?)),
+            None => None,
+        };
+        let idempotency_window = Duration::from_std(config.idempotency_window)
+            .unwrap_or_else(|_| Duration::max_value());
+
+        Ok(Jobs {
+            statuses: Arc::new(Mutex::new(HashMap::new())),
+            cancellations: Arc::new(Mutex::new(HashMap::new())),
+            idempotency_keys: Arc::new(Mutex::new(IdempotencyKeys::new(idempotency_window))),
+            queue: Arc::new(Mutex::new(Queue::new(
+                config.max_concurrent_jobs,
+                config.max_queued_jobs,
+            ))),
+            store,
+            config,
+        })
+    }
+
+    fn logger(&self) -> &Logger {
+        &self.config.logger
+    }
+
+    /// Register (and persist) a new job in the `Queued` state.
+    pub(crate) fn create(&self, spec: &JobSpec) -> Result<Job, failure::Error> {
+        let now = Utc::now();
+        let id = JobId::new();
+        let status = JobStatus {
+            id,
+            state: JobState::Queued,
+            created_at: now,
+            updated_at: now,
+            restarts: 0,
+            metadata: spec.metadata(),
+            summary: None,
+        };
+
+        if let Some(store) = &self.store {
+            store
+                .insert(&status, spec)
+                .context("Could not persist the job")?;
+        }
+
+        let pruned = {
+            let mut statuses = self.statuses.lock().expect("jobs lock poisoned");
+            statuses.insert(id, status);
+            prune_finished(&mut statuses, now)
+        };
+
+        for id in pruned {
+            self.forget_stored(&id);
+        }
+
+        Ok(Job {
+            id,
+            metadata: spec.metadata(),
+            jobs: Some(self.clone()),
+        })
+    }
+
+    /// Accept a job: register it and queue it for processing.
+    ///
+    /// When an idempotency key is given, either as `idempotency_key` (from the `Idempotency-Key`
+    /// header) or in the spec, a submission of the same spec with the same key within the
+    /// idempotency window returns the original job instead.
+    pub(crate) fn submit(
+        &self,
+        mut spec: JobSpec,
+        idempotency_key: Option<String>,
+    ) -> Result<JobId, EndpointError> {
+        // Fail now rather than at upload time if the metadata does not fit the key pattern.
+        self.config
+            .key_pattern
+            .folder("", spec.metadata().as_ref(), Utc::now())
+            .map_err(|cause| EndpointError::UnprocessableEntity { cause })?;
+
+        let key = match (idempotency_key, spec.idempotency_key()) {
+            (Some(header), Some(field)) if header != field => {
+                return Err(EndpointError::UnprocessableEntity {
+                    cause: format_err!(
+                        "The Idempotency-Key header and the idempotency_key field differ."
+                    ),
+                })
+            }
+            (header, field) => match header.or(field) {
+                Some(key) => key,
+                None => return self.submit_new(spec),
+            },
+        };
+
+        spec.set_idempotency_key(Some(key.clone()));
+        let fingerprint = spec.fingerprint()?;
+
+        // The key is reserved under the lock, and the job created without it, so the other
+        // submissions are not held up by the disk.
+        let lookup = self
+            .idempotency_keys
+            .lock()
+            .expect("idempotency keys lock poisoned")
+            .lookup(&key, &fingerprint, Utc::now());
+
+        match lookup {
+            Lookup::New => (),
+            Lookup::Existing(id) => {
+                info!(
+                    self.logger(),
+                    "Idempotency key {:?} was already used for job {}.", key, id
+                );
+                return Ok(id);
+            }
+            Lookup::Pending => {
+                return Err(EndpointError::Conflict {
+                    cause: format_err!(
+                        "A request with the idempotency key {:?} is being processed, please \
+                         retry later.",
+                        key
+                    ),
+                })
+            }
+            Lookup::Conflict => {
+                return Err(EndpointError::UnprocessableEntity {
+                    cause: format_err!(
+                        "The idempotency key {:?} was already used for a different request.",
+                        key
+                    ),
+                })
+            }
+        }
+
+        let submission = self.submit_new(spec);
+        let mut idempotency_keys = self
+            .idempotency_keys
+            .lock()
+            .expect("idempotency keys lock poisoned");
+
+        match submission {
+            Ok(id) => {
+                idempotency_keys.insert(key, fingerprint, id, Utc::now());
+                Ok(id)
+            }
+            Err(err) => {
+                idempotency_keys.release(&key);
+                Err(err)
+            }
+        }
+    }
+
+    fn submit_new(&self, spec: JobSpec) -> Result<JobId, EndpointError> {
+        let job = self.create(&spec)?;
+
+        match self.prepare(&job, spec) {
+            Ok(task) => self.enqueue(&job, task)?,
+            Err(err) => {
+                self.forget(&job.id());
+                return Err(err.into());
+            }
+        }
+
+        Ok(job.id())
+    }
+
+    /// Build the task for a job, and make it cancellable.
+    fn prepare(&self, job: &Job, spec: JobSpec) -> Result<Task, failure::Error> {
+        let id = job.id();
+        let callback_url = spec.callback_url();
+        let (task, abort_handle) = abortable(spec.into_task(self.config.clone(), job.clone())?);
+
+        self.cancellations
+            .lock()
+            .expect("cancellations lock poisoned")
+            .insert(
+                id,
+                Cancellation {
+                    abort_handle,
+                    callback_url,
+                },
+            );
+
+        let cancellations = self.cancellations.clone();
+
+        Ok(async move {
+            // An aborted task was already reported by `cancel`.
+            task.await.ok();
+            cancellations
+                .lock()
+                .expect("cancellations lock poisoned")
+                .remove(&id);
+        }
+            .boxed())
+    }
+
+    /// Cancel a queued or running job, and report it to the callback URL.
+    ///
+    /// Dropping the task of a running job kills the child process it is waiting for, if any,
+    /// and deletes its workspace. The debugging output is not uploaded.
+    pub(crate) fn cancel(&self, id: &JobId) -> Result<JobStatus, EndpointError> {
+        let (status, summary) = {
+            let mut statuses = self.statuses.lock().expect("jobs lock poisoned");
+            let status = statuses.get_mut(id).ok_or_else(|| EndpointError::NotFound {
+                cause: format_err!("No job with id {}", id),
+            })?;
+
+            if status.state.is_finished() {
+                return Err(EndpointError::Conflict {
+                    cause: format_err!("The job is already {:?}.", status.state),
+                });
+            }
+
+            let summary = Summary::new(
+                *id,
+                status.metadata.clone(),
+                Outcome::Cancelled {
+                    cancelled_at: Utc::now(),
+                },
+            );
+
+            status.state = JobState::Cancelled;
+            status.updated_at = Utc::now();
+            status.summary = Some(summary.clone());
+            // Under the lock, so an earlier state cannot be persisted over this one.
+            self.persist(status);
+            (status.clone(), summary)
+        };
+
+        let cancellation = self
+            .cancellations
+            .lock()
+            .expect("cancellations lock poisoned")
+            .remove(id);
+
+        if let Some(Cancellation {
+            abort_handle,
+            callback_url,
+        }) = cancellation
+        {
+            abort_handle.abort();
+            // A queued job gives its place back right away rather than when a worker gets to it.
+            let pending = self
+                .queue
+                .lock()
+                .expect("queue lock poisoned")
+                .remove(id);
+            drop(pending);
+            info!(self.logger(), "Cancelled job {}.", id);
+
+            let logger = self.logger().new(o!("job_id" => id.to_string()));
+
+            crate::utils::callbacks::report_cancellation(
+                self.config.clone(),
+                logger,
+                callback_url,
+                summary,
+            );
+        }
+
+        Ok(status)
+    }
+
+    /// Restore the jobs persisted by a previous run. Finished jobs become visible through
+    /// `GET /jobs/:id` again, and the others are queued.
+    ///
+    /// Jobs that were being processed when papers stopped are started over, unless that already
+    /// happened `MAX_RESTARTS` times: they are then reported as failed, since they are likely to
+    /// be the reason papers went down.
+    ///
+    /// This must be called from within the tokio runtime.
+    pub fn resume(&self) -> Result<(), failure::Error> {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+
+        for (mut status, spec) in store.load_all(self.logger())? {
+            let id = status.id;
+
+            if let Some(key) = spec.idempotency_key() {
+                let fingerprint = spec.fingerprint()?;
+                self.idempotency_keys
+                    .lock()
+                    .expect("idempotency keys lock poisoned")
+                    .insert(key, fingerprint, id, status.created_at);
+            }
+
+            if status.state.is_finished() {
+                self.statuses
+                    .lock()
+                    .expect("jobs lock poisoned")
+                    .insert(id, status);
+                continue;
+            }
+
+            let job = Job {
+                id,
+                metadata: status.metadata.clone(),
+                jobs: Some(self.clone()),
+            };
+
+            if status.state != JobState::Queued {
+                status.restarts += 1;
+            }
+
+            if status.restarts > MAX_RESTARTS {
+                self.statuses
+                    .lock()
+                    .expect("jobs lock poisoned")
+                    .insert(id, status);
+                self.fail_interrupted(job, spec);
+                continue;
+            }
+
+            info!(self.logger(), "Resuming job {} ({:?}).", id, status.state);
+            status.state = JobState::Queued;
+            status.updated_at = Utc::now();
+            {
+                let mut statuses = self.statuses.lock().expect("jobs lock poisoned");
+                self.persist(&status);
+                statuses.insert(id, status);
+            }
+
+            match self.prepare(&job, spec) {
+                Ok(task) => self.resume_task(id, task),
+                Err(err) => error!(self.logger(), "Could not resume job {}: {:?}.", id, err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Report a job that keeps getting interrupted as failed.
+    fn fail_interrupted(&self, job: Job, spec: JobSpec) {
+        let error = format_err!(
+            "The job was interrupted {} times and will not be retried.",
+            MAX_RESTARTS + 1
+        );
+        let summary = job.summary(Outcome::Failure {
+            code: ErrorCode::Interrupted,
+            backtrace: String::new(),
+            error: display_error(&error),
+            s3_folder: String::new(),
+        });
+        job.finish(JobState::Failed, summary.clone());
+
+        crate::utils::callbacks::report_failure(
+            self.config.clone(),
+            self.logger().clone(),
+            &error,
+            summary,
+            spec.callback_url(),
+        );
+    }
+
+    /// The current status of the job with the given id, if it is known.
+    pub(crate) fn get(&self, id: &JobId) -> Option<JobStatus> {
+        self.statuses
+            .lock()
+            .expect("jobs lock poisoned")
+            .get(id)
+            .cloned()
+    }
+
+    /// Run `task` for `job` as soon as a worker is available. When the queue is full, the job is
+    /// forgotten and an error asking the client to retry later is returned.
+    fn enqueue(&self, job: &Job, task: Task) -> Result<(), EndpointError> {
+        let push = self
+            .queue
+            .lock()
+            .expect("queue lock poisoned")
+            .push(job.id(), task);
+
+        match push {
+            Push::Start(task) => self.spawn(task),
+            Push::Queued => (),
+            Push::Full(_) => {
+                warn!(
+                    self.logger(),
+                    "Job queue is full, rejecting job {}. \
+                     To change it set PAPERS_MAX_CONCURRENT_JOBS or PAPERS_MAX_QUEUED_JOBS.",
+                    job.id(),
+                );
+                self.forget(&job.id());
+                self.cancellations
+                    .lock()
+                    .expect("cancellations lock poisoned")
+                    .remove(&job.id());
+                return Err(EndpointError::ServiceUnavailable {
+                    cause: format_err!("Too many jobs in the queue, please retry later."),
+                    retry_after: QUEUE_FULL_RETRY_AFTER,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Like `enqueue`, but for jobs that were already accepted, so they are never rejected.
+    fn resume_task(&self, id: JobId, task: Task) {
+        let push = self
+            .queue
+            .lock()
+            .expect("queue lock poisoned")
+            .push_unbounded(id, task);
+
+        if let Push::Start(task) = push {
+            self.spawn(task);
+        }
+    }
+
+    /// Take a worker for a synchronous render, so those count against
+    /// `PAPERS_MAX_CONCURRENT_JOBS` as well. The worker is given back when the returned value is
+    /// dropped. It fails with a 503 when all workers are busy: synchronous renders are not
+    /// queued.
+    pub(crate) fn worker(&self) -> Result<Worker, EndpointError> {
+        if self.queue.lock().expect("queue lock poisoned").try_start() {
+            return Ok(Worker { jobs: self.clone() });
+        }
+
+        warn!(
+            self.logger(),
+            "All workers are busy, rejecting a synchronous render. \
+             To change it set PAPERS_MAX_CONCURRENT_JOBS."
+        );
+
+        Err(EndpointError::ServiceUnavailable {
+            cause: format_err!("Too many documents are being rendered, please retry later."),
+            retry_after: QUEUE_FULL_RETRY_AFTER,
+        })
+    }
+
+    /// Spawn `task` on the executor. The worker moves on to the next queued task when it is done.
+    fn spawn(&self, task: Task) {
+        let worker = Worker { jobs: self.clone() };
+
+        tokio::executor::spawn(
+            async move {
+                task.await;
+                // Dropping the worker picks up the next task.
+                drop(worker);
+            }
+                .unit_error()
+                .boxed()
+                .compat(),
+        );
+    }
+
+    /// Remove a job from the registry and the store.
+    fn forget(&self, id: &JobId) {
+        self.statuses
+            .lock()
+            .expect("jobs lock poisoned")
+            .remove(id);
+        self.forget_stored(id);
+    }
+
+    fn forget_stored(&self, id: &JobId) {
+        if let Some(store) = &self.store {
+            store
+                .remove(id)
+                .map_err(|err| warn!(self.logger(), "Could not remove job {}: {:?}.", id, err))
+                .ok();
+        }
+    }
+
+    /// Write the status to the store. Called with the `statuses` lock held, so the writes happen
+    /// in the same order as the state transitions.
+    fn persist(&self, status: &JobStatus) {
+        if let Some(store) = &self.store {
+            store
+                .update(status)
+                .map_err(|err| {
+                    warn!(
+                        self.logger(),
+                        "Could not persist the state of job {}: {:?}.", status.id, err
+                    )
+                })
+                .ok();
+        }
+    }
+
+    fn update(&self, id: &JobId, state: JobState, summary: Option<Summary>) {
+        let mut statuses = self.statuses.lock().expect("jobs lock poisoned");
+
+        match statuses.get_mut(id) {
+            // A cancelled job may still be running for a moment.
+            Some(status) if status.state.is_finished() => (),
+            Some(status) => {
+                status.state = state;
+                status.updated_at = Utc::now();
+                if summary.is_some() {
+                    status.summary = summary;
+                }
+                // Under the lock, so a concurrent cancellation is not persisted over.
+                self.persist(status);
+            }
+            None => (),
+        }
+    }
+}
+
+/// Hands the next queued task to the executor when dropped, so a panicking task does not take
+/// a worker down with it.
+pub(crate) struct Worker {
+    jobs: Jobs,
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let next = self.jobs.queue.lock().expect("queue lock poisoned").finish();
+
+        if let Some(task) = next {
+            self.jobs.spawn(task);
+        }
+    }
+}
+
+/// Forget about the jobs that finished a long time ago, so the registry does not grow forever.
+/// Returns the ids of the forgotten jobs.
+fn prune_finished(statuses: &mut HashMap<JobId, JobStatus>, now: DateTime<Utc>) -> Vec<JobId> {
+    let horizon = now - Duration::hours(FINISHED_JOBS_RETENTION_HOURS);
+    let expired: Vec<JobId> = statuses
+        .values()
+        .filter(|status| status.state.is_finished() && status.updated_at <= horizon)
+        .map(|status| status.id)
+        .collect();
+
+    for id in &expired {
+        statuses.remove(id);
+    }
+
+    expired
+}
+
+/// A handle to a single job, used by the [`Renderer`](crate::papers::Renderer) and the
+/// [`Merger`](crate::papers::Merger) to report progress.
+#[derive(Clone, Debug)]
+pub struct Job {
+    id: JobId,
+    /// The `metadata` of the spec, to be echoed in the summary.
+    metadata: Option<Metadata>,
+    /// `None` for jobs that are not tracked in a registry, like previews.
+    jobs: Option<Jobs>,
+}
+
+impl Job {
+    /// A job that is not visible through `GET /jobs/:id`.
+    pub fn untracked() -> Self {
+        Job {
+            id: JobId::new(),
+            metadata: None,
+            jobs: None,
+        }
+    }
+
+    pub fn id(&self) -> JobId {
+        self.id
+    }
+
+    /// Whether the job is registered, unlike the ones of `POST /preview` and `POST /render`,
+    /// which are never uploaded.
+    pub fn is_tracked(&self) -> bool {
+        self.jobs.is_some()
+    }
+
+    /// The `metadata` of the spec.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The summary of the job with the given outcome.
+    pub fn summary(&self, outcome: Outcome) -> Summary {
+        Summary::new(self.id, self.metadata.clone(), outcome)
+    }
+
+    /// Move the job to `state`.
+    pub fn set_state(&self, state: JobState) {
+        if let Some(jobs) = &self.jobs {
+            jobs.update(&self.id, state, None);
+        }
+    }
+
+    /// Move the job to its final state, with the summary that was sent to the callback URL.
+    pub fn finish(&self, state: JobState, summary: Summary) {
+        if let Some(jobs) = &self.jobs {
+            jobs.update(&self.id, state, Some(summary));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn jobs_with(config: Config) -> Jobs {
+        Jobs::new(Arc::new(config)).unwrap()
+    }
+
+    /// Run `test` where tasks, like the delivery of the callbacks, can be spawned. They never
+    /// run: the runtime is dropped with them.
+    fn with_executor<F: FnOnce()>(test: F) {
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(futures01::future::lazy(|| {
+                test();
+                Ok::<(), ()>(())
+            }))
+            .unwrap();
+    }
+
+    fn spec() -> JobSpec {
+        JobSpec::Merge(
+            serde_json::from_value(json!({
+                "assets_urls": ["http://example.com/doc.pdf"],
+                "callback_url": "http://example.com/callback",
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn jobs_are_created_queued() {
+        let jobs = jobs_with(Config::for_tests());
+        let job = jobs.create(&spec()).unwrap();
+
+        let status = jobs.get(&job.id()).unwrap();
+        assert_eq!(status.state, JobState::Queued);
+        assert!(status.summary.is_none());
+    }
+
+    #[test]
+    fn finishing_a_job_records_the_summary() {
+        let jobs = jobs_with(Config::for_tests());
+        let job = jobs.create(&spec()).unwrap();
+
+        job.set_state(JobState::Compiling);
+        assert_eq!(jobs.get(&job.id()).unwrap().state, JobState::Compiling);
+
+        job.finish(
+            JobState::Succeeded,
+            job.summary(Outcome::Success {
+                file: "https://example.com/out.pdf".to_owned(),
+                s3_folder: "folder".to_owned(),
+                s3_key: "folder/out.pdf".to_owned(),
+                expires_at: Some(Utc::now()),
+                filename: "out.pdf".to_owned(),
+                size: 0,
+                sha256: String::new(),
+                pages: None,
+                durations: Default::default(),
+            }),
+        );
+        let status = jobs.get(&job.id()).unwrap();
+        assert_eq!(status.state, JobState::Succeeded);
+        assert!(status.summary.is_some());
+    }
+
+    #[test]
+    fn old_finished_jobs_are_pruned() {
+        let jobs = jobs_with(Config::for_tests());
+        let job = jobs.create(&spec()).unwrap();
+        job.set_state(JobState::Failed);
+
+        let later = Utc::now() + Duration::hours(FINISHED_JOBS_RETENTION_HOURS + 1);
+        let pruned = prune_finished(&mut jobs.statuses.lock().unwrap(), later);
+
+        assert_eq!(pruned, vec![job.id()]);
+        assert!(jobs.get(&job.id()).is_none());
+    }
+
+    #[test]
+    fn jobs_are_rejected_and_forgotten_when_the_queue_is_full() {
+        let jobs = jobs_with(
+            Config::for_tests()
+                .with_max_concurrent_jobs(1)
+                .with_max_queued_jobs(0),
+        );
+        let _worker = jobs.worker().unwrap();
+        let job = jobs.create(&spec()).unwrap();
+        let task = jobs.prepare(&job, spec()).unwrap();
+
+        match jobs.enqueue(&job, task) {
+            Err(EndpointError::ServiceUnavailable { retry_after, .. }) => {
+                assert_eq!(retry_after, QUEUE_FULL_RETRY_AFTER)
+            }
+            other => panic!("expected the job to be rejected, got {:?}", other),
+        }
+        assert!(jobs.get(&job.id()).is_none());
+        assert!(jobs.cancellations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn synchronous_renders_take_a_worker() {
+        let jobs = jobs_with(Config::for_tests().with_max_concurrent_jobs(1));
+
+        let worker = jobs.worker().unwrap();
+        match jobs.worker() {
+            Err(EndpointError::ServiceUnavailable { retry_after, .. }) => {
+                assert_eq!(retry_after, QUEUE_FULL_RETRY_AFTER)
+            }
+            Err(other) => panic!("expected a 503, got {:?}", other),
+            Ok(_) => panic!("expected the render to be rejected"),
+        }
+
+        drop(worker);
+        assert!(jobs.worker().is_ok());
+    }
+
+    #[test]
+    fn cancelled_jobs_give_their_place_in_the_queue_back() {
+        with_executor(|| {
+            let jobs = jobs_with(
+                Config::for_tests()
+                    .with_max_concurrent_jobs(1)
+                    .with_max_queued_jobs(1),
+            );
+            let worker = jobs.worker().unwrap();
+
+            let queued = jobs.submit(spec(), None).unwrap();
+            match jobs.submit(spec(), None) {
+                Err(EndpointError::ServiceUnavailable { .. }) => (),
+                other => panic!("expected the job to be rejected, got {:?}", other),
+            }
+
+            jobs.cancel(&queued).unwrap();
+            assert!(jobs.submit(spec(), None).is_ok());
+
+            // Giving the worker back would start the queued job.
+            std::mem::forget(worker);
+        });
+    }
+
+    fn spec_with_key(key: &str, callback_url: &str) -> JobSpec {
+        JobSpec::Merge(
+            serde_json::from_value(json!({
+                "assets_urls": ["http://example.com/doc.pdf"],
+                "callback_url": callback_url,
+                "idempotency_key": key,
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn resubmissions_with_the_same_idempotency_key_return_the_original_job() {
+        // The jobs stay queued while the only worker is taken.
+        let jobs = jobs_with(Config::for_tests().with_max_concurrent_jobs(1));
+        let worker = jobs.worker().unwrap();
+        let callback_url = "http://example.com/callback";
+
+        let id = jobs
+            .submit(spec_with_key("abc", callback_url), None)
+            .unwrap();
+
+        assert_eq!(
+            jobs.submit(spec_with_key("abc", callback_url), None).unwrap(),
+            id
+        );
+        assert_eq!(jobs.submit(spec(), Some("abc".to_owned())).unwrap(), id);
+        assert_ne!(
+            jobs.submit(spec_with_key("def", callback_url), None).unwrap(),
+            id
+        );
+
+        // Giving the worker back would start the queued jobs.
+        std::mem::forget(worker);
+    }
+
+    #[test]
+    fn reused_idempotency_keys_with_a_different_spec_are_rejected() {
+        let jobs = jobs_with(Config::for_tests().with_max_concurrent_jobs(1));
+        let worker = jobs.worker().unwrap();
+
+        let callback_url = "http://example.com/callback";
+
+        jobs.submit(spec_with_key("abc", callback_url), None)
+            .unwrap();
+
+        match jobs.submit(spec_with_key("abc", "http://example.com/other"), None) {
+            Err(EndpointError::UnprocessableEntity { .. }) => (),
+            other => panic!("expected the job to be rejected, got {:?}", other),
+        }
+        match jobs.submit(spec_with_key("abc", callback_url), Some("def".to_owned())) {
+            Err(EndpointError::UnprocessableEntity { .. }) => (),
+            other => panic!("expected the job to be rejected, got {:?}", other),
+        }
+
+        std::mem::forget(worker);
+    }
+
+    #[test]
+    fn idempotency_keys_of_rejected_submissions_can_be_retried() {
+        let jobs = jobs_with(
+            Config::for_tests()
+                .with_max_concurrent_jobs(1)
+                .with_max_queued_jobs(0),
+        );
+        let _worker = jobs.worker().unwrap();
+        let callback_url = "http://example.com/callback";
+
+        for _ in 0..2 {
+            match jobs.submit(spec_with_key("abc", callback_url), None) {
+                Err(EndpointError::ServiceUnavailable { .. }) => (),
+                other => panic!("expected the job to be rejected, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn specs_without_the_metadata_of_the_key_pattern_are_rejected() {
+        let key_pattern =
+            crate::storage::KeyPattern::parse("{tenant}/{job_id}/{filename}").unwrap();
+        let jobs = jobs_with(
+            Config::for_tests()
+                .with_max_concurrent_jobs(1)
+                .with_key_pattern(key_pattern),
+        );
+        let worker = jobs.worker().unwrap();
+
+        match jobs.submit(spec(), None) {
+            Err(EndpointError::UnprocessableEntity { .. }) => (),
+            other => panic!("expected the job to be rejected, got {:?}", other),
+        }
+
+        let mut spec = spec();
+        if let JobSpec::Merge(merge_spec) = &mut spec {
+            merge_spec.metadata = serde_json::from_value(json!({ "tenant": "acme" })).unwrap();
+        }
+        assert!(jobs.submit(spec, None).is_ok());
+
+        std::mem::forget(worker);
+    }
+
+    #[test]
+    fn finished_jobs_are_restored_from_the_store() {
+        let data_dir = mktemp::Temp::new_dir().unwrap();
+        let config = || Config::for_tests().with_data_dir(data_dir.to_path_buf());
+
+        let jobs = jobs_with(config());
+        let job = jobs.create(&spec()).unwrap();
+        job.set_state(JobState::Succeeded);
+
+        let restarted = jobs_with(config());
+        assert!(restarted.get(&job.id()).is_none());
+
+        restarted.resume().unwrap();
+        assert_eq!(
+            restarted.get(&job.id()).unwrap().state,
+            JobState::Succeeded
+        );
+    }
+
+    #[test]
+    fn finished_jobs_do_not_change_state() {
+        let jobs = jobs_with(Config::for_tests());
+        let job = jobs.create(&spec()).unwrap();
+
+        job.set_state(JobState::Failed);
+        job.set_state(JobState::Uploading);
+
+        assert_eq!(jobs.get(&job.id()).unwrap().state, JobState::Failed);
+    }
+
+    #[test]
+    fn cancelled_jobs_stay_cancelled_in_the_store() {
+        let data_dir = mktemp::Temp::new_dir().unwrap();
+        let config = || Config::for_tests().with_data_dir(data_dir.to_path_buf());
+
+        let jobs = jobs_with(config());
+        let job = jobs.create(&spec()).unwrap();
+        jobs.cancel(&job.id()).unwrap();
+        job.set_state(JobState::Compiling);
+
+        let restarted = jobs_with(config());
+        restarted.resume().unwrap();
+        assert_eq!(
+            restarted.get(&job.id()).unwrap().state,
+            JobState::Cancelled
+        );
+    }
+
+    #[test]
+    fn finished_jobs_cannot_be_cancelled() {
+        let jobs = jobs_with(Config::for_tests());
+        let job = jobs.create(&spec()).unwrap();
+        job.set_state(JobState::Succeeded);
+
+        match jobs.cancel(&job.id()) {
+            Err(EndpointError::Conflict { .. }) => (),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        match jobs.cancel(&JobId::new()) {
+            Err(EndpointError::NotFound { .. }) => (),
+            other => panic!("expected a not found error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cancelled_jobs_are_reported_as_such() {
+        let jobs = jobs_with(Config::for_tests());
+        let job = jobs.create(&spec()).unwrap();
+
+        let status = jobs.cancel(&job.id()).unwrap();
+
+        assert_eq!(status.state, JobState::Cancelled);
+        match status.summary {
+            Some(Summary {
+                outcome: Outcome::Cancelled { .. },
+                ..
+            }) => (),
+            other => panic!("expected a cancellation summary, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn metadata_is_echoed_in_the_status_and_summary() {
+        let jobs = jobs_with(Config::for_tests());
+        let spec = JobSpec::Merge(
+            serde_json::from_value(json!({
+                "assets_urls": ["http://example.com/doc.pdf"],
+                "callback_url": "http://example.com/callback",
+                "metadata": { "order_id": "A-12" },
+            }))
+            .unwrap(),
+        );
+        let job = jobs.create(&spec).unwrap();
+        let metadata = json!({ "order_id": "A-12" }).as_object().cloned();
+
+        assert_eq!(jobs.get(&job.id()).unwrap().metadata, metadata);
+
+        let status = jobs.cancel(&job.id()).unwrap();
+        assert_eq!(status.summary.unwrap().metadata, metadata);
+    }
+
+    #[test]
+    fn untracked_jobs_are_not_registered() {
+        let job = Job::untracked();
+        job.set_state(JobState::Compiling);
+        assert!(jobs_with(Config::for_tests()).get(&job.id()).is_none());
+    }
+}
@@ -1,13 +1,15 @@
-use crate::papers::{MergeSpec, Workspace};
+use crate::papers::{
+    elapsed_ms, Durations, ErrorCode, Job, JobError, JobState, MergeSpec, WithErrorCode, Workspace,
+};
 use crate::prelude::*;
+use crate::utils::process::{output_with_limits, ResourceLimits};
+use crate::utils::sandbox::{self, Sandbox};
 use std::future::Future;
-use futures::compat::*;
-use futures::{FutureExt, StreamExt, TryFutureExt};
+use futures::{FutureExt, StreamExt};
 use slog::{debug, error, Logger};
 use std::pin::Pin;
 use std::path::*;
-use std::process::Command;
-use tokio_process::CommandExt;
+use std::time::{Duration, Instant};
 
 pub struct Merger {
     /// The blueprint for the merged document.
@@ -19,11 +21,11 @@ pub struct Merger {
 }
 
 impl Merger {
-    pub fn new(config: Arc<Config>, merge_spec: MergeSpec) -> Result<Self, failure::Error> {
+    pub fn new(config: Arc<Config>, job: Job, merge_spec: MergeSpec) -> Result<Self, failure::Error> {
         let logger = config.logger.clone();
-        let workspace = Workspace::new(logger, config)?;
+        let workspace = Workspace::new(logger, config, job)?;
 
-        let output_path = workspace.temp_dir_path().join(&merge_spec.output_filename);
+        let output_path = workspace.temp_dir_path().join(merge_spec.output_filename());
 
         Ok(Merger {
             merge_spec,
@@ -48,13 +50,16 @@ impl Merger {
     /// This method takes ownership because it is meant to be used to create futures to be
     /// spawned in the background.
     pub async fn merge_documents(self) -> Result<(), ()> {
-        self.merge_documents_inner()
-            .or_else(|err| self.report_failure(err))
-            .await
-            .ok();
+        let failed = match self.merge_documents_inner().await {
+            Ok(()) => false,
+            Err(err) => {
+                self.report_failure(err).await.ok();
+                true
+            }
+        };
 
         self.workspace
-            .upload_workspace()
+            .upload_workspace(&self.merge_spec.s3, &self.merge_spec.workspace, failed)
             .await
             .map_err(|err| {
                 error!(
@@ -67,36 +72,71 @@ impl Merger {
         Ok(())
     }
 
-    async fn merge_documents_inner(&self) -> Result<(), failure::Error> {
+    async fn merge_documents_inner(&self) -> Result<(), JobError> {
+        let mut durations = Durations::default();
+
         // Download
+        self.workspace.job().set_state(JobState::Downloading);
+        let started = Instant::now();
         let asset_paths = self
             .download_assets()
             .await
-            .context("Error downloading assets.")?;
+            .context("Error downloading assets.")
+            .error_code(ErrorCode::AssetDownloadFailed)?;
+        durations.download_ms = Some(elapsed_ms(started));
 
         // Convert
+        self.workspace.job().set_state(JobState::Compiling);
+        let started = Instant::now();
         let converted_paths = self
             .convert_assets_to_pdf(asset_paths)
             .await
-            .context("Error converting asset file to PDF.")?;
+            .context("Error converting asset file to PDF.")
+            .error_code(ErrorCode::ConversionFailed)?;
 
         // Merge
         self.merge_pdf(converted_paths)
             .await
-            .context("Error merging the PDFs.")?;
+            .context("Error merging the PDFs.")
+            .error_code(ErrorCode::MergeFailed)?;
+        durations.merge_ms = Some(elapsed_ms(started));
+
+        let document = self
+            .workspace
+            .inspect_pdf(&self.output_path)
+            .await
+            .error_code(ErrorCode::InternalError)?;
 
         // Upload the merged PDF
-        let presigned_url = self
+        self.workspace.job().set_state(JobState::Uploading);
+        let started = Instant::now();
+        let uploaded = self
             .workspace
-            .upload_to_s3(self.output_path.to_owned())
-            .await?;
+            .upload(
+                self.output_path.to_owned(),
+                &self.merge_spec.s3,
+                self.merge_spec.url_expiration(),
+            )
+            .await
+            .error_code(ErrorCode::UploadFailed)?;
+        durations.upload_ms = Some(elapsed_ms(started));
 
         // Report success
         let callback_url = self.merge_spec.callback_url();
 
-        self.workspace
-            .report_success(presigned_url, &callback_url)
-            .await
+        // The job succeeded even when the callback URL never gets the summary, which is then a
+        // dead letter. Reporting a failure would contradict it.
+        if let Err(err) = self
+            .workspace
+            .report_success(document, uploaded, durations, callback_url)
+        {
+            error!(
+                self.workspace.logger(),
+                "Error reporting success to callback_url: {:?}.", err
+            );
+        }
+
+        Ok(())
     }
 
     /// Convert non-PDF files to PDF with imagemagick, and returns the path of the converted
@@ -107,13 +147,18 @@ impl Merger {
     ) -> Result<Vec<PathBuf>, failure::Error> {
         let mut futures = futures::stream::FuturesOrdered::new();
 
+        let timeout = self.workspace.config().convert_timeout;
+        let limits = self.workspace.config().resource_limits;
+        let sandbox = self.workspace.config().sandbox.clone();
+
         for path in asset_paths.into_iter() {
             let logger = self.workspace.logger().clone();
+            let sandbox = sandbox.clone();
             let to_pdf = move |path: PathBuf| -> Pin<Box<dyn Future<Output=Result<PathBuf, failure::Error>> + Send>> {
                 match path.extension() {
                     Some(extension) if extension == "pdf" => futures::future::ready(Ok(path)).boxed(),
                     None => futures::future::ready(Ok(path)).boxed(),
-                    Some(_) => image_to_pdf(logger, path.clone()).boxed(),
+                    Some(_) => image_to_pdf(logger, path.clone(), timeout, limits, sandbox).boxed(),
                 }
             };
             futures.push(to_pdf(path));
@@ -148,13 +193,13 @@ impl Merger {
         paths.into_iter().collect()
     }
 
-    async fn report_failure(&self, error: failure::Error) -> Result<(), ()> {
+    async fn report_failure(&self, error: JobError) -> Result<(), ()> {
         error!(
             self.workspace.logger(),
-            "Error merging documents: {:?}.", error
+            "Error merging documents ({:?}): {:?}.", error.code, error.error
         );
         let callback_url = self.merge_spec.callback_url();
-        match self.workspace.report_failure(error, callback_url).await {
+        match self.workspace.report_failure(error, callback_url) {
             Ok(()) => (),
             Err(err) => error!(self.workspace.logger(), "Documents merge failed: {:?}.", err),
         }
@@ -163,14 +208,21 @@ impl Merger {
     }
 
     async fn merge_pdf(&self, converted_paths: Vec<PathBuf>) -> Result<(), failure::Error> {
-        let output = Command::new("pdfunite")
-            .current_dir(&self.workspace.temp_dir_path())
-            .args(converted_paths)
-            .arg(&self.merge_spec.output_filename)
-            .output_async()
-            .compat()
-            .await
-            .context("Error merging PDFs")?;
+        let config = self.workspace.config();
+        let mut command = sandbox::command(
+            config.sandbox.as_ref(),
+            "pdfunite",
+            self.workspace.temp_dir_path(),
+        );
+        command.args(converted_paths).arg(&self.output_path);
+        let output = output_with_limits(
+            command,
+            "pdfunite",
+            config.pdfunite_timeout,
+            config.resource_limits,
+        )
+        .await
+        .context("Error merging PDFs")?;
 
         let stdout_and_err = crate::utils::process::whole_output(&output).expect("output is utf8");
 
@@ -198,12 +250,19 @@ impl Merger {
 async fn image_to_pdf(
     logger: Logger,
     original_file_path: PathBuf,
+    timeout: Duration,
+    limits: ResourceLimits,
+    sandbox: Option<Sandbox>,
 ) -> Result<PathBuf, failure::Error> {
     // "/tmp/something.jpeg" -> "something"
     let stem = original_file_path.file_stem().expect("Invalid path");
     let final_path = original_file_path.with_file_name(format!("{}.pdf", stem.to_string_lossy()));
-    let output = Command::new("convert")
-        .current_dir(&original_file_path.parent().expect("Invalid path"))
+    let mut command = sandbox::command(
+        sandbox.as_ref(),
+        "convert",
+        original_file_path.parent().expect("Invalid path"),
+    );
+    command
         .arg(original_file_path)
         .arg("-resize")
         .arg("595x842")
@@ -217,9 +276,8 @@ async fn image_to_pdf(
         .arg("72")
         .arg("-page")
         .arg("A4")
-        .arg(&final_path)
-        .output_async()
-        .compat()
+        .arg(&final_path);
+    let output = output_with_limits(command, "convert", timeout, limits)
         .await
         .context("Error while converting image to pdf")?;
 
@@ -1,15 +1,33 @@
 use crate::papers::uri::PapersUri;
+use crate::papers::{Metadata, WorkspaceOptions};
 use crate::prelude::*;
+use crate::storage::ObjectSettings;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct MergeSpec {
     #[serde(default = "default_assets")]
     assets_urls: Vec<PapersUri>,
     callback_url: PapersUri,
-    #[serde(default = "default_output_filename")]
-    pub output_filename: String,
+    /// See [`output_filename()`](MergeSpec::output_filename).
+    #[serde(default)]
+    output_filename: Option<String>,
+    /// Echoed in the summary of the job.
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+    /// Makes retried submissions return the original job.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// The storage class and tags of the uploaded objects.
+    #[serde(default)]
+    pub s3: ObjectSettings,
+    /// When and how to upload the workspace.
+    #[serde(default)]
+    pub workspace: WorkspaceOptions,
+    /// See [`url_expiration()`](MergeSpec::url_expiration).
+    #[serde(default)]
+    url_expiration: Option<u64>,
 }
 
 fn default_assets() -> Vec<PapersUri> {
@@ -29,6 +47,19 @@ impl MergeSpec {
         self.callback_url.0.to_string()
     }
 
+    /// The requested name of the merged PDF, or `out_<current time>.pdf`.
+    pub fn output_filename(&self) -> String {
+        self.output_filename
+            .clone()
+            .unwrap_or_else(default_output_filename)
+    }
+
+    /// How long the URL to the merged PDF should be valid, if the request asks for a shorter
+    /// validity than the default.
+    pub fn url_expiration(&self) -> Option<std::time::Duration> {
+        self.url_expiration.map(std::time::Duration::from_secs)
+    }
+
     /// Validate that the specification is consistent, and that it can be expected to succeed.
     ///
     /// The error is intended for consumption by the client of the service.
@@ -38,6 +69,13 @@ impl MergeSpec {
             return Err(self.assets_count_error());
         }
 
+        // The URL would already be expired when the callback gets it.
+        if self.url_expiration == Some(0) {
+            return Err(EndpointError::UnprocessableEntity {
+                cause: format_err!("url_expiration should be at least 1 second."),
+            });
+        }
+
         Ok(())
     }
 
@@ -81,4 +119,22 @@ mod tests {
 
         panic!("did not validate that asset_urls is not empty");
     }
+
+    #[test]
+    fn merge_spec_validate_url_expiration() {
+        let spec: MergeSpec = serde_json::from_value(json!({
+            "assets_urls": ["https://example.com/a.pdf"],
+            "callback_url": "https://example.com/callback",
+            "url_expiration": 0,
+        }))
+        .unwrap();
+
+        match spec.validate() {
+            Err(EndpointError::UnprocessableEntity { cause }) => assert_eq!(
+                cause.to_string(),
+                "url_expiration should be at least 1 second."
+            ),
+            _ => panic!("did not validate that url_expiration is at least 1 second"),
+        }
+    }
 }
@@ -1,15 +1,27 @@
+mod compilation;
 mod document_spec;
+mod engine;
+mod idempotency;
+mod job_store;
+mod jobs;
 mod merge;
 mod merge_spec;
+mod queue;
 mod renderer;
 mod summary;
 mod uri;
 mod workspace;
 
-pub(crate) use self::document_spec::DocumentSpec;
+pub(crate) use self::compilation::Compiler;
+pub(crate) use self::document_spec::{DocumentSpec, RenderSpec};
+pub use self::engine::{Engine, EngineCommand, Engines};
+pub use self::jobs::Jobs;
+pub(crate) use self::jobs::{Job, JobId, JobSpec, JobState, JobStatus};
 pub(crate) use self::merge::Merger;
 pub(crate) use self::merge_spec::MergeSpec;
 pub(crate) use self::renderer::Renderer;
-pub(crate) use self::summary::Summary;
+pub(crate) use self::summary::{
+    elapsed_ms, Durations, ErrorCode, JobError, Metadata, Outcome, Summary, WithErrorCode,
+};
 pub(crate) use self::uri::PapersUri;
-pub(crate) use self::workspace::Workspace;
+pub(crate) use self::workspace::{Workspace, WorkspaceOptions, WorkspaceUpload};
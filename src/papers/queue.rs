@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+/// The outcome of [`Queue::push`](Queue::push).
+#[derive(Debug, PartialEq)]
+pub enum Push<T> {
+    /// There is a free worker, the task should be started right away.
+    Start(T),
+    /// All workers are busy, the task will be returned by [`Queue::finish`](Queue::finish) when
+    /// one of them is done, unless it is [removed](Queue::remove) first.
+    Queued,
+    /// The queue is full, the task is handed back.
+    Full(T),
+}
+
+/// A bounded FIFO of tasks waiting for one of a limited number of workers. The tasks are
+/// identified by a key, so they can be removed before they start.
+///
+/// This only does the bookkeeping: spawning the tasks is up to the caller.
+#[derive(Debug)]
+pub struct Queue<K, T> {
+    pending: VecDeque<(K, T)>,
+    running: usize,
+    max_running: usize,
+    max_pending: usize,
+}
+
+impl<K: PartialEq, T> Queue<K, T> {
+    /// A queue with `max_running` workers, at least one so the tasks are not queued forever, and
+    /// room for `max_pending` tasks.
+    pub fn new(max_running: usize, max_pending: usize) -> Self {
+        Queue {
+            pending: VecDeque::new(),
+            running: 0,
+            max_running: max_running.max(1),
+            max_pending,
+        }
+    }
+
+    /// Add a task. The caller must start it if `Push::Start` is returned.
+    pub fn push(&mut self, key: K, task: T) -> Push<T> {
+        if self.running < self.max_running {
+            self.running += 1;
+            Push::Start(task)
+        } else if self.pending.len() < self.max_pending {
+            self.pending.push_back((key, task));
+            Push::Queued
+        } else {
+            Push::Full(task)
+        }
+    }
+
+    /// Add a task even if the queue is full. The caller must start it if `Push::Start` is
+    /// returned.
+    pub fn push_unbounded(&mut self, key: K, task: T) -> Push<T> {
+        if self.running < self.max_running {
+            self.running += 1;
+            Push::Start(task)
+        } else {
+            self.pending.push_back((key, task));
+            Push::Queued
+        }
+    }
+
+    /// Take a free worker for work done outside of the queue, which must be given back with
+    /// [`Queue::finish`](Queue::finish). Returns whether there was one.
+    pub fn try_start(&mut self) -> bool {
+        if self.running < self.max_running {
+            self.running += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take a pending task out of the queue, freeing its place. Returns `None` if there is no
+    /// pending task with this key, for instance because it already started.
+    pub fn remove(&mut self, key: &K) -> Option<T> {
+        let index = self
+            .pending
+            .iter()
+            .position(|(pending, _)| pending == key)?;
+        self.pending.remove(index).map(|(_, task)| task)
+    }
+
+    /// Signal that a running task is done. The worker is handed the next pending task, if any,
+    /// which the caller must start.
+    pub fn finish(&mut self) -> Option<T> {
+        let next = self.pending.pop_front().map(|(_, task)| task);
+
+        if next.is_none() {
+            self.running -= 1;
+        }
+
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks_start_until_workers_are_busy() {
+        let mut queue = Queue::new(2, 1);
+
+        assert_eq!(queue.push(1, 1), Push::Start(1));
+        assert_eq!(queue.push(2, 2), Push::Start(2));
+        assert_eq!(queue.push(3, 3), Push::Queued);
+        assert_eq!(queue.push(4, 4), Push::Full(4));
+        assert_eq!(queue.push_unbounded(5, 5), Push::Queued);
+    }
+
+    #[test]
+    fn finished_tasks_free_workers_in_order() {
+        let mut queue = Queue::new(1, 2);
+
+        queue.push(1, "a");
+        queue.push(2, "b");
+        queue.push(3, "c");
+
+        assert_eq!(queue.finish(), Some("b"));
+        assert_eq!(queue.finish(), Some("c"));
+        assert_eq!(queue.finish(), None);
+        assert_eq!(queue.push(4, "d"), Push::Start("d"));
+    }
+
+    #[test]
+    fn there_is_always_a_worker() {
+        let mut queue = Queue::new(0, 1);
+
+        assert_eq!(queue.push(1, "a"), Push::Start("a"));
+        assert_eq!(queue.push(2, "b"), Push::Queued);
+    }
+
+    #[test]
+    fn workers_can_be_taken_without_a_task() {
+        let mut queue = Queue::new(1, 1);
+
+        assert!(queue.try_start());
+        assert!(!queue.try_start());
+        assert_eq!(queue.push(1, "a"), Push::Queued);
+        assert_eq!(queue.finish(), Some("a"));
+        assert_eq!(queue.finish(), None);
+        assert!(queue.try_start());
+    }
+
+    #[test]
+    fn removed_tasks_free_their_place() {
+        let mut queue = Queue::new(1, 1);
+
+        assert_eq!(queue.push(1, "a"), Push::Start("a"));
+        assert_eq!(queue.push(2, "b"), Push::Queued);
+        assert_eq!(queue.push(3, "c"), Push::Full("c"));
+
+        assert_eq!(queue.remove(&1), None);
+        assert_eq!(queue.remove(&2), Some("b"));
+        assert_eq!(queue.push(3, "c"), Push::Queued);
+        assert_eq!(queue.finish(), Some("c"));
+    }
+}
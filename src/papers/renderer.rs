@@ -1,10 +1,12 @@
-use crate::papers::{DocumentSpec, Workspace};
+use crate::papers::{
+    elapsed_ms, Compiler, DocumentSpec, Durations, ErrorCode, Job, JobError, JobState,
+    WithErrorCode, Workspace,
+};
 use crate::prelude::*;
 use futures::{compat::*, StreamExt};
 use slog::{debug, error};
-use std::process::Command;
+use std::time::Instant;
 use tokio::{fs::File, io::AsyncWrite};
-use tokio_process::CommandExt;
 
 /// The name of the downloaded template inside our Tera instance.
 const TEMPLATE_NAME: &str = "template";
@@ -12,6 +14,8 @@ const TEMPLATE_NAME: &str = "template";
 pub struct Renderer {
     /// The manifest for the document to render.
     document_spec: DocumentSpec,
+    /// Runs the LaTeX engine chosen by the spec.
+    compiler: Compiler,
     /// The path to the rendered document.
     output_path: std::path::PathBuf,
     /// The path to the downloaded template.
@@ -23,21 +27,27 @@ pub struct Renderer {
 }
 
 impl Renderer {
-    pub fn new(config: Arc<Config>, document_spec: DocumentSpec) -> Result<Self, failure::Error> {
-        let workspace = Workspace::new(config.logger.clone(), config)?;
+    pub fn new(
+        config: Arc<Config>,
+        job: Job,
+        document_spec: DocumentSpec,
+    ) -> Result<Self, failure::Error> {
+        let compiler = Compiler::new(&config, document_spec.engine)?;
+        let workspace = Workspace::new(config.logger.clone(), config, job)?;
 
-        let output_path = workspace
-            .temp_dir_path()
-            .join(&document_spec.output_filename);
+        let output_filename = document_spec.output_filename();
+
+        let output_path = workspace.temp_dir_path().join(&output_filename);
 
         let template_path = workspace
             .temp_dir_path()
-            .join(document_spec.output_filename.replace("pdf", "tex"));
+            .join(output_filename.replace("pdf", "tex"));
 
         Ok(Renderer {
             tera: crate::utils::templating::make_tera(),
             workspace,
             document_spec,
+            compiler,
             output_path,
             template_path,
         })
@@ -62,17 +72,22 @@ impl Renderer {
             "Generating PDF with document spec: {:?}.", self.document_spec
         );
 
-        match self.render_inner().await {
+        let failed = match self.render_inner().await {
             // it worked, move on
-            Ok(()) => (),
+            Ok(()) => false,
             // it failed -> report it
             Err(err) => {
                 self.report_failure(err).await.ok();
+                true
             }
-        }
+        };
 
         self.workspace
-            .upload_workspace()
+            .upload_workspace(
+                &self.document_spec.s3,
+                &self.document_spec.workspace,
+                failed,
+            )
             .await
             .map_err(|err| {
                 error!(
@@ -85,29 +100,84 @@ impl Renderer {
         Ok(())
     }
 
-    async fn render_inner(&mut self) -> Result<(), failure::Error> {
-        // First download the template and populate it
-        self.download_and_register_template().await?;
-        self.render_template().await?;
+    /// Generate the PDF without uploading it or reporting to the callback URL, for synchronous
+    /// renders. Returns the path to the PDF in the workspace.
+    pub async fn render_to_file(&mut self) -> Result<&std::path::Path, JobError> {
+        self.compile(&mut Durations::default()).await?;
 
-        // Download the assets and save them in the temporary directory
-        self.download_assets().await?;
+        Ok(&self.output_path)
+    }
 
-        // Then run latex
-        self.run_latex().await?;
+    async fn render_inner(&mut self) -> Result<(), JobError> {
+        let mut durations = Durations::default();
+
+        self.compile(&mut durations).await?;
+
+        let document = self
+            .workspace
+            .inspect_pdf(&self.output_path)
+            .await
+            .error_code(ErrorCode::InternalError)?;
 
         // Upload the resulting PDF and construct a presigned URL to it
-        let presigned_url = self
+        self.workspace.job().set_state(JobState::Uploading);
+        let started = Instant::now();
+        let uploaded = self
             .workspace
-            .upload_to_s3(self.output_path.to_owned())
-            .await?;
+            .upload(
+                self.output_path.to_owned(),
+                &self.document_spec.s3,
+                self.document_spec.url_expiration(),
+            )
+            .await
+            .error_code(ErrorCode::UploadFailed)?;
+        durations.upload_ms = Some(elapsed_ms(started));
 
         // Report to the callback URL
         let callback_url = self.document_spec.callback_url();
-        self
+        // The job succeeded even when the callback URL never gets the summary, which is then a
+        // dead letter. Reporting a failure would contradict it.
+        if let Err(err) = self
             .workspace
-            .report_success(presigned_url, &callback_url)
-            .await?;
+            .report_success(document, uploaded, durations, callback_url)
+        {
+            error!(
+                self.workspace.logger(),
+                "Error reporting success to callback_url: {:?}.", err
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Download the template and the assets, render the template and run LaTeX.
+    async fn compile<'a>(&'a mut self, durations: &'a mut Durations) -> Result<(), JobError> {
+        // First download the template and populate it
+        self.workspace.job().set_state(JobState::Downloading);
+        let started = Instant::now();
+        self.download_and_register_template()
+            .await
+            .error_code(ErrorCode::TemplateDownloadFailed)?;
+        let template_download_ms = elapsed_ms(started);
+
+        let started = Instant::now();
+        self.render_template()
+            .await
+            .error_code(ErrorCode::TemplateError)?;
+        durations.template_render_ms = Some(elapsed_ms(started));
+
+        // Download the assets and save them in the temporary directory
+        let started = Instant::now();
+        self.download_assets()
+            .await
+            .error_code(ErrorCode::AssetDownloadFailed)?;
+        durations.download_ms = Some(template_download_ms + elapsed_ms(started));
+
+        // Then run latex
+        self.workspace.job().set_state(JobState::Compiling);
+        let started = Instant::now();
+        self.run_latex().await.error_code(ErrorCode::LatexFailed)?;
+        durations.latex_ms = Some(elapsed_ms(started));
 
         Ok(())
     }
@@ -196,39 +266,25 @@ impl Renderer {
             self.template_path().exists()
         );
 
-        debug!(&self.workspace.logger(), "Spawning latex.");
-        let latex_out = Command::new("xelatex")
-            .current_dir(&self.workspace.temp_dir_path())
-            .arg("-interaction=nonstopmode")
-            .arg("-file-line-error")
-            .arg("-shell-restricted")
-            .arg(self.template_path())
-            .output_async()
-            .compat()
+        self.compiler
+            .compile(
+                &self.workspace.logger(),
+                self.workspace.temp_dir_path(),
+                self.template_path(),
+            )
             .await
-            .context("Error generating PDF")?;
-
-        let stdout = String::from_utf8(latex_out.stdout)?;
-
-        if !latex_out.status.success() {
-            return Err(format_err!("LaTeX failed. Stdout:\n{}", stdout));
-        }
-
-        debug!(&self.workspace.logger(), "LaTeX succeeded. Stdout:\n{}", stdout);
-
-        Ok(())
     }
 
     /// Report failure and move on.
-    async fn report_failure(&self, error: failure::Error) -> Result<(), ()> {
+    async fn report_failure(&self, error: JobError) -> Result<(), ()> {
         error!(
             self.workspace.logger(),
-            "Error rendering document: {:?}.", error,
+            "Error rendering document ({:?}): {:?}.", error.code, error.error,
         );
 
         let callback_url = self.document_spec.callback_url();
 
-        match self.workspace.report_failure(error, callback_url).await {
+        match self.workspace.report_failure(error, callback_url) {
             Ok(()) => (),
             Err(err) => error!(
                 self.workspace.logger(),
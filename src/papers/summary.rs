@@ -1,45 +1,271 @@
+use crate::papers::JobId;
+use crate::utils::process::TimedOut;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::time::Instant;
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "lowercase", untagged)]
-pub enum Summary {
-    File {
+/// The version of the summary format. It is bumped on every breaking change.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The free-form `metadata` object of the document and merge specs.
+pub type Metadata = serde_json::Map<String, serde_json::Value>;
+
+/// What is sent to the callback URL when a job is finished.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Summary {
+    pub schema_version: u32,
+    pub job_id: JobId,
+    /// The `metadata` the job was submitted with, echoed verbatim.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl Summary {
+    pub fn new(job_id: JobId, metadata: Option<Metadata>, outcome: Outcome) -> Self {
+        Summary {
+            schema_version: SCHEMA_VERSION,
+            job_id,
+            metadata,
+            outcome,
+        }
+    }
+}
+
+/// How a job ended, serialized as the `status` field of the summary.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase", tag = "status")]
+pub enum Outcome {
+    Success {
+        /// The presigned URL to the document.
         file: String,
         s3_folder: String,
+        /// The key of the document in the bucket.
+        s3_key: String,
+        /// When the presigned URL expires. Public URLs do not expire.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<DateTime<Utc>>,
+        filename: String,
+        /// The size of the document in bytes.
+        size: u64,
+        /// The hex-encoded SHA-256 of the document.
+        sha256: String,
+        /// The number of pages of the document, when it could be determined.
+        pages: Option<u32>,
+        durations: Durations,
     },
-    Error {
+    Failure {
+        code: ErrorCode,
         error: String,
         backtrace: String,
         s3_folder: String,
     },
+    Cancelled {
+        cancelled_at: DateTime<Utc>,
+    },
+}
+
+/// A machine-readable description of what went wrong, based on the stage where a job failed.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    TemplateDownloadFailed,
+    AssetDownloadFailed,
+    TemplateError,
+    LatexFailed,
+    ConversionFailed,
+    MergeFailed,
+    UploadFailed,
+    CallbackFailed,
+    /// The job was interrupted by restarts too many times.
+    Interrupted,
+    /// A program (LaTeX, `pdfunite` or `convert`) ran longer than its timeout and was killed.
+    TimedOut,
+    InternalError,
+}
+
+/// An error that made a job fail, along with the code reported in the summary.
+#[derive(Debug)]
+pub struct JobError {
+    pub code: ErrorCode,
+    pub error: failure::Error,
+}
+
+/// Tag the error of a result with an [`ErrorCode`](ErrorCode). Errors caused by a
+/// [`TimedOut`](crate::utils::process::TimedOut) program get the `TimedOut` code instead.
+pub trait WithErrorCode<T> {
+    fn error_code(self, code: ErrorCode) -> Result<T, JobError>;
+}
+
+impl<T, E: Into<failure::Error>> WithErrorCode<T> for Result<T, E> {
+    fn error_code(self, code: ErrorCode) -> Result<T, JobError> {
+        self.map_err(|error| {
+            let error = error.into();
+            let timed_out = error
+                .iter_chain()
+                .any(|cause| cause.downcast_ref::<TimedOut>().is_some());
+
+            JobError {
+                code: if timed_out { ErrorCode::TimedOut } else { code },
+                error,
+            }
+        })
+    }
+}
+
+/// How long each phase of a job took, in milliseconds. Phases that are not part of the job are
+/// left out.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Durations {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_render_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latex_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_ms: Option<u64>,
+}
+
+/// The milliseconds elapsed since `start`, for [`Durations`](Durations).
+pub fn elapsed_ms(start: Instant) -> u64 {
+    start.elapsed().as_millis() as u64
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Summary;
+    use super::*;
+
+    fn job_id() -> JobId {
+        "7f9e8c4a-3b1d-4e5f-9a2b-6c8d0e1f2a3b".parse().unwrap()
+    }
 
     #[test]
     fn it_serializes_errors_as_expected() {
-        let summary = Summary::Error {
-            backtrace: "".to_owned(),
-            error: "meow".to_owned(),
-            s3_folder: "/the/bucket/the/key".to_owned(),
+        let summary = Summary::new(
+            job_id(),
+            None,
+            Outcome::Failure {
+                code: ErrorCode::LatexFailed,
+                backtrace: "".to_owned(),
+                error: "meow".to_owned(),
+                s3_folder: "/the/bucket/the/key".to_owned(),
+            },
+        );
+        assert_eq!(
+            &serde_json::to_string(&summary).unwrap(),
+            "{\"schema_version\":2,\"job_id\":\"7f9e8c4a-3b1d-4e5f-9a2b-6c8d0e1f2a3b\",\
+             \"status\":\"failure\",\"code\":\"latex_failed\",\"error\":\"meow\",\"backtrace\":\"\",\
+             \"s3_folder\":\"/the/bucket/the/key\"}"
+        );
+    }
+
+    #[test]
+    fn timed_out_programs_get_their_own_code() {
+        let timed_out = TimedOut {
+            program: "xelatex".to_owned(),
+            timeout: std::time::Duration::from_secs(120),
         };
+        let result: Result<(), _> = Err(failure::Error::from(timed_out).context("Compiling"));
+
+        let error = result.error_code(ErrorCode::LatexFailed).unwrap_err();
+        assert_eq!(error.code, ErrorCode::TimedOut);
+
+        let result: Result<(), _> = Err(failure::format_err!("LaTeX failed"));
+        let error = result.error_code(ErrorCode::LatexFailed).unwrap_err();
+        assert_eq!(error.code, ErrorCode::LatexFailed);
+    }
+
+    #[test]
+    fn it_serializes_cancellations_as_expected() {
+        let summary = Summary::new(
+            job_id(),
+            None,
+            Outcome::Cancelled {
+                cancelled_at: "2019-10-28T09:12:03Z".parse().unwrap(),
+            },
+        );
         assert_eq!(
             &serde_json::to_string(&summary).unwrap(),
-            "{\"error\":\"meow\",\"backtrace\":\"\",\"s3_folder\":\"/the/bucket/the/key\"}"
+            "{\"schema_version\":2,\"job_id\":\"7f9e8c4a-3b1d-4e5f-9a2b-6c8d0e1f2a3b\",\
+             \"status\":\"cancelled\",\"cancelled_at\":\"2019-10-28T09:12:03Z\"}"
         );
     }
 
     #[test]
     fn it_serializes_success_as_expected() {
-        let summary = Summary::File {
-            file: "https://example.com/the_file.pdf".to_owned(),
-            s3_folder: "/my/bucket/my/key".to_owned(),
-        };
+        let summary = Summary::new(
+            job_id(),
+            None,
+            Outcome::Success {
+                file: "https://example.com/the_file.pdf".to_owned(),
+                s3_folder: "/my/bucket/my/key".to_owned(),
+                s3_key: "/my/bucket/my/key/the_file.pdf".to_owned(),
+                expires_at: Some("2019-10-29T09:12:03Z".parse().unwrap()),
+                filename: "the_file.pdf".to_owned(),
+                size: 3,
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                    .to_owned(),
+                pages: Some(2),
+                durations: Durations {
+                    download_ms: Some(120),
+                    latex_ms: Some(2000),
+                    upload_ms: Some(80),
+                    ..Durations::default()
+                },
+            },
+        );
         assert_eq!(
             &serde_json::to_string(&summary).unwrap(),
-            "{\"file\":\"https://example.com/the_file.pdf\",\"s3_folder\":\"/my/bucket/my/key\"}"
+            "{\"schema_version\":2,\"job_id\":\"7f9e8c4a-3b1d-4e5f-9a2b-6c8d0e1f2a3b\",\
+             \"status\":\"success\",\"file\":\"https://example.com/the_file.pdf\",\
+             \"s3_folder\":\"/my/bucket/my/key\",\"s3_key\":\"/my/bucket/my/key/the_file.pdf\",\
+             \"expires_at\":\"2019-10-29T09:12:03Z\",\"filename\":\"the_file.pdf\",\"size\":3,\
+             \"sha256\":\"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\",\
+             \"pages\":2,\"durations\":{\"download_ms\":120,\"latex_ms\":2000,\"upload_ms\":80}}"
         );
     }
+
+    #[test]
+    fn metadata_is_echoed() {
+        let metadata = serde_json::json!({ "order_id": 42, "tags": ["a"] });
+        let summary = Summary::new(
+            job_id(),
+            metadata.as_object().cloned(),
+            Outcome::Cancelled {
+                cancelled_at: "2019-10-28T09:12:03Z".parse().unwrap(),
+            },
+        );
+        assert_eq!(
+            &serde_json::to_string(&summary).unwrap(),
+            "{\"schema_version\":2,\"job_id\":\"7f9e8c4a-3b1d-4e5f-9a2b-6c8d0e1f2a3b\",\
+             \"metadata\":{\"order_id\":42,\"tags\":[\"a\"]},\
+             \"status\":\"cancelled\",\"cancelled_at\":\"2019-10-28T09:12:03Z\"}"
+        );
+    }
+
+    #[test]
+    fn summaries_roundtrip() {
+        let summary = Summary::new(
+            job_id(),
+            None,
+            Outcome::Failure {
+                code: ErrorCode::UploadFailed,
+                backtrace: "".to_owned(),
+                error: "meow".to_owned(),
+                s3_folder: "folder".to_owned(),
+            },
+        );
+        let parsed: Summary =
+            serde_json::from_str(&serde_json::to_string(&summary).unwrap()).unwrap();
+
+        assert_eq!(parsed.job_id, job_id());
+        match parsed.outcome {
+            Outcome::Failure { code, .. } => assert_eq!(code, ErrorCode::UploadFailed),
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
 }
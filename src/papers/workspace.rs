@@ -1,7 +1,56 @@
+use crate::papers::{Durations, Job, JobError, JobState, Outcome};
 use crate::prelude::*;
+use crate::storage::{encode_key, ObjectSettings, PutOptions, UploadedDocument};
+use crate::utils::archive::Compression;
 use crate::utils::http::{client_response_body_to_file, extract_filename_from_uri};
+use crate::utils::pdf::PdfInfo;
+use chrono::Utc;
 use futures::compat::*;
-use slog::{debug, Logger};
+use serde::{Deserialize, Serialize};
+use slog::{debug, o, Logger};
+use std::sync::Mutex;
+
+/// When the workspace of a job is uploaded for debugging.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceUpload {
+    /// After every job.
+    Always,
+    /// Only after failed jobs.
+    OnFailure,
+    /// Never.
+    Never,
+}
+
+impl std::str::FromStr for WorkspaceUpload {
+    type Err = failure::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "always" => Ok(WorkspaceUpload::Always),
+            "on_failure" => Ok(WorkspaceUpload::OnFailure),
+            "never" => Ok(WorkspaceUpload::Never),
+            other => Err(format_err!(
+                "Unknown workspace upload policy {:?}, expected always, on_failure or never",
+                other
+            )),
+        }
+    }
+}
+
+/// The `workspace` field of the specs, overriding the configured upload of the workspace.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct WorkspaceOptions {
+    /// When to upload the workspace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<WorkspaceUpload>,
+    /// Whether to leave the downloaded template and assets out of the archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_assets: Option<bool>,
+    /// How to compress the archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+}
 
 /// A wrapper around a temporary directory where we download and manipulate files.
 pub struct Workspace {
@@ -9,6 +58,8 @@ pub struct Workspace {
     client: reqwest::r#async::Client,
     /// The app config.
     config: Arc<Config>,
+    /// The job this workspace was created for.
+    job: Job,
     /// The temporary directory for the task.
     ///
     /// Since [`mktemp::Temp`](mktemp::Temp) implements [`Drop`](Drop) by deleting the
@@ -17,22 +68,37 @@ pub struct Workspace {
     /// The local logger for the task. This may contain context for more useful logging. The
     /// Workspace will additionally log to a file in the temporary directory.
     logger: Logger,
-    /// The directory we will upload to inside the destination S3 bucket.
-    s3_dir_name: String,
+    /// The directory we will upload to inside the storage. The untracked jobs are never
+    /// uploaded, so they have none, and the key pattern may need metadata they lack.
+    s3_dir_name: Option<String>,
+    /// The files downloaded into the workspace, which can be left out of the archive.
+    downloads: Mutex<Vec<std::path::PathBuf>>,
 }
 
 impl Workspace {
     /// Construct a `Workspace`. The `base_logger` will be used as a base to construct the file +
     /// stderr logger used in the `Workspace`.
-    pub fn new(base_logger: Logger, config: Arc<Config>) -> Result<Self, failure::Error> {
+    pub fn new(base_logger: Logger, config: Arc<Config>, job: Job) -> Result<Self, failure::Error> {
         let temp_dir = mktemp::Temp::new_dir().context("Could not create a temporary directory")?;
+        let base_logger = base_logger.new(o!("job_id" => job.id().to_string()));
         let logger = crate::utils::logging::file_logger(base_logger, &temp_dir.to_path_buf());
+        let s3_dir_name = if job.is_tracked() {
+            Some(
+                config
+                    .key_pattern
+                    .folder(&job.id().to_string(), job.metadata(), Utc::now())?,
+            )
+        } else {
+            None
+        };
         Ok(Workspace {
             config: config.clone(),
+            job,
             client: reqwest::r#async::Client::new(),
             logger,
             temp_dir,
-            s3_dir_name: crate::utils::s3::s3_dir_name(),
+            s3_dir_name,
+            downloads: Mutex::new(Vec::new()),
         })
     }
 
@@ -43,6 +109,24 @@ impl Workspace {
         self.logger.clone()
     }
 
+    /// The configuration of the service.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The job this workspace was created for.
+    pub fn job(&self) -> &Job {
+        &self.job
+    }
+
+    /// The directory of the job in the storage.
+    fn folder(&self) -> Result<&str, failure::Error> {
+        match &self.s3_dir_name {
+            Some(folder) => Ok(folder),
+            None => Err(format_err!("The job {} is not uploaded", self.job.id())),
+        }
+    }
+
     /// The path to the workspace's temporary directory.
     pub fn temp_dir_path(&self) -> &std::path::Path {
         self.temp_dir.as_ref()
@@ -95,60 +179,198 @@ impl Workspace {
             .await
             .context("Error downloading asset")?;
 
+        self.downloads
+            .lock()
+            .expect("workspace downloads lock poisoned")
+            .push(dest_path.clone());
+
         Ok(dest_path)
     }
 
-    pub async fn report_success<'a>(
-        &'a self,
-        presigned_url: String,
-        callback_url: &'a str,
+    /// Record the success of the job, and report it to the callback URL in the background.
+    pub fn report_success(
+        &self,
+        document: PdfInfo,
+        uploaded: UploadedDocument,
+        durations: Durations,
+        callback_url: String,
     ) -> Result<(), failure::Error> {
+        let outcome = Outcome::Success {
+            file: uploaded.url,
+            s3_folder: self.folder()?.to_owned(),
+            s3_key: uploaded.key,
+            expires_at: uploaded.expires_at,
+            filename: document.filename,
+            size: document.size,
+            sha256: document.sha256,
+            pages: document.pages,
+            durations,
+        };
+        let summary = self.job.summary(outcome);
+
+        self.job.finish(JobState::Succeeded, summary.clone());
+
         crate::utils::callbacks::report_success(
+            self.config.clone(),
             self.logger(),
             callback_url,
-            self.s3_dir_name.clone(),
-            presigned_url,
-        )
-        .await
+            summary,
+        );
+
+        Ok(())
     }
 
-    /// Report errors to the callback URL.
-    pub async fn report_failure(
+    /// Record the failure of the job, and report it to the callback URL in the background.
+    pub fn report_failure(
         &self,
-        error: failure::Error,
+        error: JobError,
         callback_url: String,
     ) -> Result<(), failure::Error> {
+        let JobError { code, error } = error;
+
+        // For the callback, we want the user-facing version of the error.
+        let outcome = Outcome::Failure {
+            code,
+            backtrace: error.backtrace().to_string(),
+            error: display_error(&error),
+            s3_folder: self.folder()?.to_owned(),
+        };
+        let summary = self.job.summary(outcome);
+
+        self.job.finish(JobState::Failed, summary.clone());
+
         crate::utils::callbacks::report_failure(
+            self.config.clone(),
             self.logger(),
-            error,
-            self.s3_dir_name.to_owned(),
-            &callback_url,
-        )
-        .await
+            &error,
+            summary,
+            callback_url,
+        );
+
+        Ok(())
     }
 
-    /// Returns a presigned URL to the uploaded file.
-    pub async fn upload_to_s3(
-        &self,
+    /// Inspect a generated PDF for the success summary.
+    pub async fn inspect_pdf<'a>(
+        &'a self,
+        file_path: &'a std::path::Path,
+    ) -> Result<PdfInfo, failure::Error> {
+        crate::utils::pdf::inspect(&self.logger, &self.config, file_path).await
+    }
+
+    /// Upload the file to the storage and return where it ended up, including a URL to
+    /// download it.
+    pub async fn upload<'a>(
+        &'a self,
         file_path: std::path::PathBuf,
-    ) -> Result<String, failure::Error> {
+        settings: &'a ObjectSettings,
+        url_expiration: Option<std::time::Duration>,
+    ) -> Result<UploadedDocument, failure::Error> {
         let filename = file_path.file_name().ok_or_else(|| {
             format_err!("missing filename in \"{}\"", file_path.to_string_lossy())
         })?;
-        let key = format!("{}/{}", &self.s3_dir_name, filename.to_string_lossy());
-        crate::utils::s3::upload_document(&self.config, self.logger(), file_path, key).await
+        let filename = filename.to_string_lossy();
+        let key = format!("{}/{}", self.folder()?, filename);
+        let options = PutOptions::for_file(&filename, settings.clone());
+
+        debug!(self.logger, "Uploading {:?} as {:?}.", file_path, key);
+        self.config.storage.put(&key, &file_path, &options).await?;
+
+        let (url, expires_at) = match &self.config.public_base_url {
+            Some(base_url) => (format!("{}/{}", base_url, encode_key(&key)), None),
+            None => {
+                // Requests can shorten the validity of the URL, but not extend it.
+                let expires_in = url_expiration
+                    .unwrap_or(self.config.url_expiration)
+                    .min(self.config.url_expiration);
+                let expires_at = Utc::now() + chrono::Duration::from_std(expires_in)?;
+
+                (
+                    self.config.storage.url(&key, expires_in).await?,
+                    Some(expires_at),
+                )
+            }
+        };
+
+        Ok(UploadedDocument {
+            key,
+            url,
+            expires_at,
+        })
     }
 
-    /// Upload the whole workspace directory to the S3 directory as `workspace.tar`.
-    pub async fn upload_workspace(&self) -> Result<(), failure::Error> {
-        let workspace_tar_key = format!("{}/{}", &self.s3_dir_name, "workspace.tar");
+    /// Archive the workspace directory and upload it to the storage directory as
+    /// `workspace.tar`, unless the upload policy says otherwise. `workspace` overrides the
+    /// configured policy.
+    pub async fn upload_workspace<'a>(
+        &'a self,
+        settings: &'a ObjectSettings,
+        workspace: &'a WorkspaceOptions,
+        failed: bool,
+    ) -> Result<(), failure::Error> {
+        let upload = workspace.upload.unwrap_or(self.config.workspace_upload);
+        let wanted = match upload {
+            WorkspaceUpload::Always => true,
+            WorkspaceUpload::OnFailure => failed,
+            WorkspaceUpload::Never => false,
+        };
+
+        if !wanted {
+            debug!(self.logger, "Not uploading the workspace ({:?}).", upload);
+            return Ok(());
+        }
 
-        crate::utils::s3::upload_workspace(
-            &self.config,
+        let exclude = if workspace
+            .exclude_assets
+            .unwrap_or(self.config.workspace_exclude_assets)
+        {
+            self.downloads
+                .lock()
+                .expect("workspace downloads lock poisoned")
+                .clone()
+        } else {
+            Vec::new()
+        };
+        let compression = workspace
+            .compression
+            .unwrap_or(self.config.workspace_compression);
+
+        let mut archive_name = compression.archive_name().to_owned();
+        let mut archive_path = crate::utils::archive::tar_workspace(
             self.logger(),
-            self.temp_dir_path(),
-            workspace_tar_key,
+            self.temp_dir_path().to_owned(),
+            exclude,
+            compression,
         )
-        .await
+        .await?;
+
+        if let Some(encryption) = &self.config.workspace_encryption {
+            archive_path = encryption
+                .encrypt(&self.logger, &self.config, archive_path.as_ref())
+                .await?;
+            archive_name = format!("{}.{}", archive_name, encryption.extension());
+        }
+
+        let key = format!("{}/{}", self.folder()?, archive_name);
+        let options = PutOptions::for_file(&archive_name, settings.clone());
+
+        self.config.storage.put(&key, &archive_path, &options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::KeyPattern;
+
+    #[test]
+    fn untracked_jobs_ignore_the_key_pattern() {
+        let pattern = KeyPattern::parse("{tenant}/{yyyy}/{mm}/{job_id}/{output_filename}").unwrap();
+        let config = Arc::new(Config::for_tests().with_key_pattern(pattern));
+
+        let workspace =
+            Workspace::new(config.logger.clone(), config.clone(), Job::untracked()).unwrap();
+
+        assert!(workspace.folder().is_err());
     }
 }
@@ -17,6 +17,16 @@ pub enum EndpointError {
         #[fail(cause)]
         cause: failure::Error,
     },
+    #[fail(display = "Not Found (404)")]
+    NotFound {
+        #[fail(cause)]
+        cause: failure::Error,
+    },
+    #[fail(display = "Conflict (409)")]
+    Conflict {
+        #[fail(cause)]
+        cause: failure::Error,
+    },
     #[fail(display = "Internal Server Error (500)")]
     InternalServerError {
         #[fail(cause)]
@@ -27,6 +37,18 @@ pub enum EndpointError {
         #[fail(cause)]
         cause: failure::Error,
     },
+    #[fail(display = "Service Unavailable (503)")]
+    ServiceUnavailable {
+        #[fail(cause)]
+        cause: failure::Error,
+        /// Seconds after which the client should retry.
+        retry_after: u32,
+    },
+    #[fail(display = "Gateway Timeout (504)")]
+    GatewayTimeout {
+        #[fail(cause)]
+        cause: failure::Error,
+    },
 }
 
 impl From<serde_json::error::Error> for EndpointError {
@@ -69,34 +91,45 @@ impl EndpointError {
     }
 
     pub(crate) fn to_response(&self) -> Response {
-        use serde_json::json;
-
         match self {
             EndpointError::Forbidden { cause } => {
-                let body = json!({
-                    "message": display_error(&cause),
-                });
-                let mut response = json_response(&body).expect("serialization error");
-                *response.status_mut() = http::StatusCode::FORBIDDEN;
-                response
+                error_response(http::StatusCode::FORBIDDEN, cause)
             }
+            EndpointError::NotFound { cause } => error_response(http::StatusCode::NOT_FOUND, cause),
+            EndpointError::Conflict { cause } => error_response(http::StatusCode::CONFLICT, cause),
             EndpointError::InternalServerError { .. } => {
                 let mut response = empty_response();
                 *response.status_mut() = http::StatusCode::INTERNAL_SERVER_ERROR;
                 response
             }
             EndpointError::UnprocessableEntity { cause } => {
-                let body = json!({
-                    "message": display_error(&cause),
-                });
-                let mut response = json_response(&body).expect("serialization error");
-                *response.status_mut() = http::StatusCode::UNPROCESSABLE_ENTITY;
+                error_response(http::StatusCode::UNPROCESSABLE_ENTITY, cause)
+            }
+            EndpointError::ServiceUnavailable { cause, retry_after } => {
+                let mut response = error_response(http::StatusCode::SERVICE_UNAVAILABLE, cause);
+                response.headers_mut().insert(
+                    http::header::RETRY_AFTER,
+                    http::header::HeaderValue::from(*retry_after),
+                );
                 response
             }
+            EndpointError::GatewayTimeout { cause } => {
+                error_response(http::StatusCode::GATEWAY_TIMEOUT, cause)
+            }
         }
     }
 }
 
+/// A JSON response with the given status, and the error as the `message`.
+fn error_response(status: http::StatusCode, cause: &failure::Error) -> Response {
+    let body = serde_json::json!({
+        "message": display_error(cause),
+    });
+    let mut response = json_response(&body).expect("serialization error");
+    *response.status_mut() = status;
+    response
+}
+
 /// Display an error and its causes in a readable way. This is meant for user-facing errors, use
 /// the [`Debug`](std::fmt::Debug) impl for logging/sentry..
 pub(crate) fn display_error(error: &failure::Error) -> String {
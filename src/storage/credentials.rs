@@ -0,0 +1,309 @@
+use futures01::future::{err, Either};
+use futures01::Future;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rusoto_credential::{
+    AutoRefreshingProvider, AutoRefreshingProviderFuture, AwsCredentials, ChainProvider,
+    CredentialsError, EnvironmentProvider, ProvideAwsCredentials,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The global endpoint of the AWS Security Token Service.
+const STS_GLOBAL_ENDPOINT: &str = "https://sts.amazonaws.com/";
+
+/// How long a request to STS may take.
+const STS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The credentials of the S3 storage, cached until they expire.
+pub(crate) struct RefreshingCredentials(AutoRefreshingProvider<CredentialsChain>);
+
+impl RefreshingCredentials {
+    pub(crate) fn new() -> Result<Self, CredentialsError> {
+        Ok(RefreshingCredentials(AutoRefreshingProvider::new(
+            CredentialsChain::new(),
+        )?))
+    }
+}
+
+impl ProvideAwsCredentials for RefreshingCredentials {
+    type Future = AutoRefreshingProviderFuture<CredentialsChain>;
+
+    fn credentials(&self) -> Self::Future {
+        self.0.credentials()
+    }
+}
+
+impl std::fmt::Debug for RefreshingCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RefreshingCredentials")
+            .field(self.0.get_ref())
+            .finish()
+    }
+}
+
+/// The AWS credentials of papers, looked up in order from:
+///
+/// 1. The `PAPERS_ACCESS_KEY_ID`, `PAPERS_SECRET_ACCESS_KEY` and `PAPERS_SESSION_TOKEN`
+///    environment variables.
+/// 2. A web identity token, when `AWS_WEB_IDENTITY_TOKEN_FILE` and `AWS_ROLE_ARN` are set, as
+///    for IAM roles for service accounts on Kubernetes.
+/// 3. The standard chain: the `AWS_` environment variables, the AWS profile, the ECS task role
+///    and the EC2 instance profile.
+#[derive(Clone, Debug)]
+pub(crate) struct CredentialsChain {
+    papers: EnvironmentProvider,
+    web_identity: Option<WebIdentityProvider>,
+    chain: ChainProvider,
+}
+
+impl CredentialsChain {
+    fn new() -> Self {
+        CredentialsChain {
+            papers: EnvironmentProvider::with_prefix("PAPERS"),
+            web_identity: WebIdentityProvider::from_env(),
+            chain: ChainProvider::new(),
+        }
+    }
+}
+
+impl ProvideAwsCredentials for CredentialsChain {
+    type Future = Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send>;
+
+    fn credentials(&self) -> Self::Future {
+        let web_identity = self.web_identity.clone();
+        let chain = self.chain.clone();
+
+        // A configured web identity is the intended source, so its errors are not hidden by
+        // falling back to the standard chain.
+        let future = self
+            .papers
+            .credentials()
+            .or_else(move |_| match web_identity {
+                Some(web_identity) => Either::A(web_identity.credentials()),
+                None => Either::B(chain.credentials()),
+            });
+
+        Box::new(future)
+    }
+}
+
+/// Exchanges a web identity token for temporary credentials with `AssumeRoleWithWebIdentity`.
+#[derive(Clone, Debug)]
+struct WebIdentityProvider {
+    token_file: String,
+    role_arn: String,
+    session_name: String,
+    endpoint: String,
+}
+
+impl WebIdentityProvider {
+    fn from_env() -> Option<Self> {
+        let region = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("PAPERS_AWS_REGION"))
+            .ok();
+        let regional = std::env::var("AWS_STS_REGIONAL_ENDPOINTS").ok();
+
+        Some(WebIdentityProvider {
+            token_file: std::env::var("AWS_WEB_IDENTITY_TOKEN_FILE").ok()?,
+            role_arn: std::env::var("AWS_ROLE_ARN").ok()?,
+            session_name: std::env::var("AWS_ROLE_SESSION_NAME")
+                .unwrap_or_else(|_| "papers".to_owned()),
+            endpoint: sts_endpoint(
+                region.as_ref().map(String::as_str),
+                regional.as_ref().map(String::as_str),
+            ),
+        })
+    }
+}
+
+/// The STS endpoint of `region`, or the global one when there is no region or
+/// `AWS_STS_REGIONAL_ENDPOINTS` is `legacy`. The China regions only have regional endpoints.
+fn sts_endpoint(region: Option<&str>, regional_endpoints: Option<&str>) -> String {
+    match region {
+        Some(region) if region.starts_with("cn-") => {
+            format!("https://sts.{}.amazonaws.com.cn/", region)
+        }
+        Some(region) if !region.is_empty() && regional_endpoints != Some("legacy") => {
+            format!("https://sts.{}.amazonaws.com/", region)
+        }
+        _ => STS_GLOBAL_ENDPOINT.to_owned(),
+    }
+}
+
+impl ProvideAwsCredentials for WebIdentityProvider {
+    type Future = Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send>;
+
+    fn credentials(&self) -> Self::Future {
+        // The token is rotated, so it is read again every time.
+        let token = match std::fs::read_to_string(&self.token_file) {
+            Ok(token) => token,
+            Err(error) => {
+                return Box::new(err(CredentialsError::new(format!(
+                    "Could not read the web identity token from {:?}: {}",
+                    self.token_file, error
+                ))))
+            }
+        };
+
+        let url = format!(
+            "{}?Action=AssumeRoleWithWebIdentity&Version=2011-06-15&RoleArn={}\
+             &RoleSessionName={}&WebIdentityToken={}",
+            self.endpoint,
+            utf8_percent_encode(&self.role_arn, NON_ALPHANUMERIC),
+            utf8_percent_encode(&self.session_name, NON_ALPHANUMERIC),
+            utf8_percent_encode(token.trim(), NON_ALPHANUMERIC),
+        );
+
+        let client = match reqwest::r#async::Client::builder()
+            .timeout(STS_TIMEOUT)
+            .build()
+        {
+            Ok(client) => client,
+            Err(error) => return Box::new(err(CredentialsError::new(error.to_string()))),
+        };
+
+        let future = client
+            .get(&url)
+            .send()
+            .and_then(|mut response| {
+                let status = response.status();
+                response.text().map(move |body| (status, body))
+            })
+            .map_err(|error| CredentialsError::new(format!("Error calling STS: {}", error)))
+            .and_then(|(status, body)| {
+                if !status.is_success() {
+                    return Err(CredentialsError::new(format!(
+                        "STS refused the web identity token ({}): {}",
+                        status, body
+                    )));
+                }
+
+                parse_assume_role_response(&body)
+            });
+
+        Box::new(future)
+    }
+}
+
+/// Extract the credentials from the XML response of `AssumeRoleWithWebIdentity`.
+fn parse_assume_role_response(body: &str) -> Result<AwsCredentials, CredentialsError> {
+    use xml::reader::{EventReader, XmlEvent};
+
+    const CREDENTIALS: &[&str] = &[
+        "AssumeRoleWithWebIdentityResponse",
+        "AssumeRoleWithWebIdentityResult",
+        "Credentials",
+    ];
+
+    let mut path: Vec<String> = Vec::new();
+    let mut fields: HashMap<String, String> = HashMap::new();
+
+    for event in EventReader::from_str(body) {
+        let event = event.map_err(|error| {
+            CredentialsError::new(format!("Invalid XML in the STS response: {}", error))
+        })?;
+
+        match event {
+            XmlEvent::StartElement { name, .. } => path.push(name.local_name),
+            XmlEvent::EndElement { .. } => {
+                path.pop();
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some((field, parents)) = path.split_last() {
+                    if parents
+                        .iter()
+                        .map(String::as_str)
+                        .eq(CREDENTIALS.iter().cloned())
+                    {
+                        fields.entry(field.clone()).or_default().push_str(&text);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut element = |name: &str| {
+        fields
+            .remove(name)
+            .map(|value| value.trim().to_owned())
+            .ok_or_else(|| CredentialsError::new(format!("Missing {} in the STS response", name)))
+    };
+
+    let expires_at = element("Expiration")?
+        .parse()
+        .map_err(|_| CredentialsError::new("Invalid Expiration in the STS response"))?;
+
+    Ok(AwsCredentials::new(
+        element("AccessKeyId")?,
+        element("SecretAccessKey")?,
+        Some(element("SessionToken")?),
+        Some(expires_at),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_assume_role_response_reads_the_credentials() {
+        let body = r#"<AssumeRoleWithWebIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleWithWebIdentityResult>
+    <Credentials>
+      <SessionToken>AQoDYXdzEE0a8ANXXXXXXXXNO1ewxE5TijQyp+IEXAMPLE</SessionToken>
+      <SecretAccessKey>wJalrXUtnFEMI/K7MDENG/bPxRfiCYzEXAMPLEKEY</SecretAccessKey>
+      <Expiration>2019-10-29T09:12:03Z</Expiration>
+      <AccessKeyId>ASgeIAIOSFODNN7EXAMPLE</AccessKeyId>
+    </Credentials>
+  </AssumeRoleWithWebIdentityResult>
+</AssumeRoleWithWebIdentityResponse>"#;
+
+        let credentials = parse_assume_role_response(body).unwrap();
+
+        assert_eq!(credentials.aws_access_key_id(), "ASgeIAIOSFODNN7EXAMPLE");
+        assert_eq!(
+            credentials.aws_secret_access_key(),
+            "wJalrXUtnFEMI/K7MDENG/bPxRfiCYzEXAMPLEKEY"
+        );
+        assert_eq!(
+            credentials.token().as_ref().unwrap(),
+            "AQoDYXdzEE0a8ANXXXXXXXXNO1ewxE5TijQyp+IEXAMPLE"
+        );
+        assert_eq!(
+            credentials.expires_at().unwrap().to_rfc3339(),
+            "2019-10-29T09:12:03+00:00"
+        );
+
+        assert!(parse_assume_role_response("<Error>Nope</Error>").is_err());
+        // Only the elements of the credentials are read, wherever they appear in the document.
+        assert!(parse_assume_role_response(
+            "<AssumeRoleWithWebIdentityResponse><AssumeRoleWithWebIdentityResult>\
+             <Credentials></Credentials>\
+             <AccessKeyId>ASgeIAIOSFODNN7EXAMPLE</AccessKeyId>\
+             </AssumeRoleWithWebIdentityResult></AssumeRoleWithWebIdentityResponse>"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn sts_endpoint_follows_the_region() {
+        assert_eq!(
+            sts_endpoint(Some("eu-central-1"), None),
+            "https://sts.eu-central-1.amazonaws.com/"
+        );
+        assert_eq!(
+            sts_endpoint(Some("eu-central-1"), Some("regional")),
+            "https://sts.eu-central-1.amazonaws.com/"
+        );
+        assert_eq!(
+            sts_endpoint(Some("eu-central-1"), Some("legacy")),
+            "https://sts.amazonaws.com/"
+        );
+        assert_eq!(
+            sts_endpoint(Some("cn-north-1"), Some("legacy")),
+            "https://sts.cn-north-1.amazonaws.com.cn/"
+        );
+        assert_eq!(sts_endpoint(None, None), "https://sts.amazonaws.com/");
+    }
+}
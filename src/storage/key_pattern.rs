@@ -0,0 +1,269 @@
+use crate::papers::Metadata;
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde_json::Value;
+
+/// The placeholders that stand for the name of the uploaded file. `{output_filename}` reads
+/// better for the PDF, but both are replaced with `workspace.tar` for the workspace.
+const FILENAME_PLACEHOLDERS: &[&str] = &["{filename}", "{output_filename}"];
+
+/// The layout of the keys of the uploaded files, like `{tenant}/{yyyy}/{mm}/{job_id}/{filename}`.
+///
+/// The pattern must end with the name of the file, so the PDF and the `workspace.tar` of a job
+/// are stored next to each other, in what the summaries call the `s3_folder`. The other
+/// placeholders are:
+///
+/// - `{job_id}`: the id of the job
+/// - `{yyyy}`, `{mm}`, `{dd}`: the date at which the job started
+/// - any other name: the field with that name in the `metadata` of the spec, which must be a
+///   string or a number
+#[derive(Clone, Debug)]
+pub struct KeyPattern {
+    folder: Vec<Part>,
+    /// What [`matches`](KeyPattern::matches) checks the keys against, compiled once since it is
+    /// called for every stored object.
+    regex: Regex,
+}
+
+impl PartialEq for KeyPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.folder == other.folder
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+impl KeyPattern {
+    /// Parse a pattern, checking that it ends with the name of the file.
+    pub fn parse(pattern: &str) -> Result<Self, failure::Error> {
+        let folder = FILENAME_PLACEHOLDERS
+            .iter()
+            .find(|placeholder| pattern.ends_with(*placeholder))
+            .map(|placeholder| &pattern[..pattern.len() - placeholder.len()])
+            .filter(|folder| folder.ends_with('/'))
+            .map(|folder| &folder[..folder.len() - 1])
+            .filter(|folder| !folder.is_empty())
+            .ok_or_else(|| {
+                format_err!(
+                    "The key pattern {:?} should end with \"/{{filename}}\" or \
+                     \"/{{output_filename}}\"",
+                    pattern
+                )
+            })?;
+
+        let mut parts = Vec::new();
+        let mut rest = folder;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format_err!("Unclosed placeholder in key pattern {:?}", pattern))?;
+            let name = &rest[start + 1..end];
+
+            if name.is_empty() || name.contains('{') {
+                return Err(format_err!(
+                    "Invalid placeholder in key pattern {:?}",
+                    pattern
+                ));
+            }
+
+            parts.push(Part::Placeholder(name.to_owned()));
+            rest = &rest[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+
+        if parts.iter().any(|part| match part {
+            Part::Literal(literal) => literal.contains('}'),
+            Part::Placeholder(_) => false,
+        }) {
+            return Err(format_err!(
+                "Unopened placeholder in key pattern {:?}",
+                pattern
+            ));
+        }
+
+        let regex = keys_regex(&parts);
+
+        Ok(KeyPattern {
+            folder: parts,
+            regex,
+        })
+    }
+
+    /// The folder of the files of a job. It fails when a placeholder refers to a `metadata`
+    /// field that is missing, or whose value cannot be used in a key.
+    pub fn folder(
+        &self,
+        job_id: &str,
+        metadata: Option<&Metadata>,
+        started_at: DateTime<Utc>,
+    ) -> Result<String, failure::Error> {
+        let mut folder = String::new();
+
+        for part in &self.folder {
+            match part {
+                Part::Literal(literal) => folder.push_str(literal),
+                Part::Placeholder(name) => {
+                    let value = match name.as_str() {
+                        "job_id" => job_id.to_owned(),
+                        "yyyy" => started_at.format("%Y").to_string(),
+                        "mm" => started_at.format("%m").to_string(),
+                        "dd" => started_at.format("%d").to_string(),
+                        field => metadata_value(metadata, field)?,
+                    };
+                    folder.push_str(&value);
+                }
+            }
+        }
+
+        Ok(folder)
+    }
+
+    /// The beginning shared by all the keys, before the first placeholder.
+    pub fn prefix(&self) -> &str {
+        match self.folder.first() {
+            Some(Part::Literal(literal)) => literal,
+            _ => "",
+        }
+    }
+
+    /// Whether `key` could have been produced by this pattern, so the files that papers did not
+    /// store are left alone.
+    pub fn matches(&self, key: &str) -> bool {
+        self.regex.is_match(key)
+    }
+}
+
+/// The regex matching the keys of the files in the folders made of `parts`.
+fn keys_regex(parts: &[Part]) -> Regex {
+    let folder: String = parts
+        .iter()
+        .map(|part| match part {
+            Part::Literal(literal) => regex::escape(literal),
+            Part::Placeholder(name) => match name.as_str() {
+                "yyyy" => "[0-9]{4}".to_owned(),
+                "mm" | "dd" => "[0-9]{2}".to_owned(),
+                _ => "[^/]+".to_owned(),
+            },
+        })
+        .collect();
+
+    Regex::new(&format!("^{}/[^/]+$", folder)).expect("key pattern regex is valid")
+}
+
+impl Default for KeyPattern {
+    fn default() -> Self {
+        KeyPattern::parse("{yyyy}/{mm}/{dd}/{job_id}/{filename}").unwrap()
+    }
+}
+
+/// The value of a metadata field for the key. It becomes a single path segment.
+fn metadata_value(metadata: Option<&Metadata>, field: &str) -> Result<String, failure::Error> {
+    let value = match metadata.and_then(|metadata| metadata.get(field)) {
+        Some(Value::String(value)) => value.clone(),
+        Some(Value::Number(value)) => value.to_string(),
+        Some(_) => {
+            return Err(format_err!(
+                "The metadata field {:?} used in the key pattern should be a string or a number",
+                field
+            ))
+        }
+        None => {
+            return Err(format_err!(
+                "The metadata field {:?} is required by the key pattern",
+                field
+            ))
+        }
+    };
+
+    if value.is_empty() || value == "." || value == ".." || value.contains('/') {
+        return Err(format_err!(
+            "The metadata field {:?} cannot be used in a key: {:?}",
+            field,
+            value
+        ));
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn metadata(value: Value) -> Metadata {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn folder_replaces_the_placeholders() {
+        let pattern = KeyPattern::parse("{tenant}/{yyyy}/{mm}/{job_id}/{output_filename}").unwrap();
+        let started_at = Utc.ymd(2019, 10, 28).and_hms(9, 12, 3);
+        let metadata = metadata(json!({ "tenant": "acme", "order": 42 }));
+
+        assert_eq!(
+            pattern.folder("abc", Some(&metadata), started_at).unwrap(),
+            "acme/2019/10/abc"
+        );
+
+        let default = KeyPattern::default();
+        assert_eq!(
+            default.folder("abc", None, started_at).unwrap(),
+            "2019/10/28/abc"
+        );
+    }
+
+    #[test]
+    fn folder_rejects_unusable_metadata() {
+        let pattern = KeyPattern::parse("tenants/{tenant}/{job_id}/{filename}").unwrap();
+        let started_at = Utc::now();
+
+        assert!(pattern.folder("abc", None, started_at).is_err());
+        for tenant in &[json!(""), json!(".."), json!("a/b"), json!({})] {
+            let metadata = metadata(json!({ "tenant": tenant }));
+            assert!(pattern.folder("abc", Some(&metadata), started_at).is_err());
+        }
+    }
+
+    #[test]
+    fn matches_recognizes_the_keys() {
+        let pattern = KeyPattern::parse("tenants/{tenant}/{job_id}/{filename}").unwrap();
+
+        assert_eq!(pattern.prefix(), "tenants/");
+        assert!(pattern.matches("tenants/acme/abc/out.pdf"));
+        assert!(!pattern.matches("tenants/acme/out.pdf"));
+        assert!(!pattern.matches("tenants/acme/abc/def/out.pdf"));
+        assert!(!pattern.matches("backups/acme/abc/out.pdf"));
+
+        assert_eq!(KeyPattern::default().prefix(), "");
+        assert!(KeyPattern::default().matches("2019/10/28/abc/workspace.tar"));
+        assert!(!KeyPattern::default().matches("logs/2019/10/28/access.log"));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_patterns() {
+        assert!(KeyPattern::parse("{job_id}").is_err());
+        assert!(KeyPattern::parse("{filename}").is_err());
+        assert!(KeyPattern::parse("{job_id/{filename}").is_err());
+        assert!(KeyPattern::parse("job_id}/{filename}").is_err());
+        assert!(KeyPattern::parse("{}/{filename}").is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::prelude::*;
+use crate::storage::{encode_key, PutOptions, Storage, StoredObject};
+use crate::utils::signing;
+use chrono::{DateTime, Utc};
+use futures::compat::Future01CompatExt;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Stores the objects in a local directory. They are served by papers itself under
+/// `GET /files/...`, with URLs signed with a secret so they expire.
+#[derive(Debug)]
+pub struct LocalStorage {
+    root: PathBuf,
+    base_url: String,
+    secret: String,
+}
+
+impl LocalStorage {
+    /// A storage writing to `root`, creating it if needed. The URLs start with `base_url`, which
+    /// should be the address papers is reachable at.
+    pub fn new(root: PathBuf, base_url: &str, secret: String) -> Result<Self, failure::Error> {
+        std::fs::create_dir_all(&root)
+            .with_context(|_| format!("Could not create the storage directory {:?}", root))?;
+
+        Ok(LocalStorage {
+            root,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            secret,
+        })
+    }
+
+    /// Where the object stored under `key` lives. Keys that would escape the root directory are
+    /// rejected.
+    fn path(&self, key: &str) -> Result<PathBuf, failure::Error> {
+        let relative = Path::new(key);
+
+        if key.is_empty() {
+            return Err(format_err!("Invalid storage key: {:?}", key));
+        }
+
+        for component in relative.components() {
+            match component {
+                Component::Normal(_) => (),
+                _ => return Err(format_err!("Invalid storage key: {:?}", key)),
+            }
+        }
+
+        Ok(self.root.join(relative))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        _options: &'a PutOptions,
+    ) -> BoxFuture<'a, Result<(), failure::Error>> {
+        async move {
+            let destination = self.path(key)?;
+
+            if let Some(parent) = destination.parent() {
+                tokio::fs::create_dir_all(parent.to_owned())
+                    .compat()
+                    .await?;
+            }
+
+            let copy = async {
+                let source = tokio::fs::File::open(path.to_owned()).compat().await?;
+                let target = tokio::fs::File::create(destination.clone())
+                    .compat()
+                    .await?;
+                tokio::io::copy(source, target).compat().await?;
+                Ok::<(), std::io::Error>(())
+            };
+            copy.await
+                .with_context(|_| format!("Could not copy {:?} to {:?}", path, destination))?;
+
+            Ok(())
+        }
+            .boxed()
+    }
+
+    fn url<'a>(
+        &'a self,
+        key: &'a str,
+        expires_in: Duration,
+    ) -> BoxFuture<'a, Result<String, failure::Error>> {
+        let expires = Utc::now().timestamp() + expires_in.as_secs() as i64;
+        let signature = signing::sign(&self.secret, expires, key.as_bytes());
+
+        futures::future::ready(Ok(format!(
+            "{}/files/{}?expires={}&signature={}",
+            self.base_url,
+            encode_key(key),
+            expires,
+            utf8_percent_encode(&signature, NON_ALPHANUMERIC),
+        )))
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), failure::Error>> {
+        async move {
+            let path = self.path(key)?;
+            tokio::fs::remove_file(path.clone()).compat().await?;
+
+            // The folders left empty are removed too, so deleted jobs leave nothing behind.
+            for parent in path.ancestors().skip(1) {
+                if parent == self.root
+                    || tokio::fs::remove_dir(parent.to_owned())
+                        .compat()
+                        .await
+                        .is_err()
+                {
+                    break;
+                }
+            }
+
+            Ok(())
+        }
+            .boxed()
+    }
+
+    fn list<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<StoredObject>, failure::Error>> {
+        async move {
+            use futures01::Stream;
+
+            let mut objects = Vec::new();
+            let mut dirs = vec![self.root.clone()];
+
+            while let Some(dir) = dirs.pop() {
+                let entries = tokio::fs::read_dir(dir)
+                    .compat()
+                    .await?
+                    .collect()
+                    .compat()
+                    .await?;
+
+                for entry in entries {
+                    let path = entry.path();
+                    let metadata = tokio::fs::metadata(path.clone()).compat().await?;
+
+                    if metadata.is_dir() {
+                        dirs.push(path);
+                    } else if let Ok(relative) = path.strip_prefix(&self.root) {
+                        let key = relative
+                            .components()
+                            .map(|component| component.as_os_str().to_string_lossy())
+                            .collect::<Vec<_>>()
+                            .join("/");
+
+                        if key.starts_with(prefix) {
+                            objects.push(StoredObject {
+                                key,
+                                last_modified: DateTime::from(metadata.modified()?),
+                            });
+                        }
+                    }
+                }
+            }
+
+            Ok(objects)
+        }
+            .boxed()
+    }
+
+    fn signed_path(&self, key: &str, expires: i64, signature: &str) -> Option<PathBuf> {
+        if expires < Utc::now().timestamp()
+            || !signing::verify(&self.secret, expires, key.as_bytes(), signature)
+        {
+            return None;
+        }
+
+        self.path(key).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryFutureExt;
+
+    fn storage(root: &Path) -> LocalStorage {
+        LocalStorage::new(
+            root.join("storage"),
+            "http://papers.local/",
+            "secret".to_owned(),
+        )
+        .unwrap()
+    }
+
+    /// Split a URL from `LocalStorage::url` into the key, the expiry and the signature.
+    fn parse_url(url: &str) -> (String, i64, String) {
+        use percent_encoding::percent_decode_str;
+
+        let url = &url["http://papers.local/files/".len()..];
+        let mut parts = url.splitn(2, '?');
+        let key = percent_decode_str(parts.next().unwrap())
+            .decode_utf8()
+            .unwrap()
+            .into_owned();
+        let mut query = parts.next().unwrap().split('&');
+        let expires = query.next().unwrap()["expires=".len()..].parse().unwrap();
+        let signature = percent_decode_str(&query.next().unwrap()["signature=".len()..])
+            .decode_utf8()
+            .unwrap()
+            .into_owned();
+
+        (key, expires, signature)
+    }
+
+    #[test]
+    fn stored_files_are_served_with_signed_urls() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let storage = storage(dir.as_ref());
+        let source = dir.as_ref().join("out.pdf");
+        std::fs::write(&source, "%PDF").unwrap();
+        let key = "2019-10-28 09:12:03 UTC/out.pdf";
+
+        // The file system is used from the blocking sections of the Tokio thread pool, which
+        // only runs futures that own their data.
+        let test = async move {
+            storage
+                .put(
+                    key,
+                    &source,
+                    &PutOptions::for_file("out.pdf", Default::default()),
+                )
+                .await
+                .unwrap();
+
+            let url = storage.url(key, Duration::from_secs(60)).await.unwrap();
+            assert!(url
+                .starts_with("http://papers.local/files/2019-10-28%2009%3A12%3A03%20UTC/out.pdf?"));
+
+            let (parsed_key, expires, signature) = parse_url(&url);
+            assert_eq!(parsed_key, key);
+
+            let path = storage.signed_path(key, expires, &signature).unwrap();
+            assert_eq!(std::fs::read(path).unwrap(), b"%PDF");
+
+            assert!(storage.signed_path(key, expires + 1, &signature).is_none());
+            assert!(storage
+                .signed_path("2019-10-28 09:12:03 UTC/other.pdf", expires, &signature)
+                .is_none());
+
+            let listed = storage.list("2019-10-28").await.unwrap();
+            assert_eq!(listed.len(), 1);
+            assert_eq!(listed[0].key, key);
+            assert!(storage.list("2019-10-29").await.unwrap().is_empty());
+
+            storage.delete(key).await.unwrap();
+            assert!(!storage.path(key).unwrap().exists());
+            assert!(!storage.path("2019-10-28 09:12:03 UTC").unwrap().exists());
+            Ok::<(), ()>(())
+        };
+
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(test.boxed().compat())
+            .unwrap();
+    }
+
+    #[test]
+    fn expired_urls_are_rejected() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let storage = storage(dir.as_ref());
+        let expires = Utc::now().timestamp() - 1;
+        let signature = signing::sign("secret", expires, b"out.pdf");
+
+        assert!(storage
+            .signed_path("out.pdf", expires, &signature)
+            .is_none());
+    }
+
+    #[test]
+    fn keys_cannot_escape_the_root() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let storage = storage(dir.as_ref());
+
+        assert!(storage.path("../out.pdf").is_err());
+        assert!(storage.path("/etc/passwd").is_err());
+        assert!(storage.path("folder/out.pdf").is_ok());
+    }
+}
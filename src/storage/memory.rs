@@ -0,0 +1,148 @@
+use crate::storage::{PutOptions, Storage, StoredObject};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Keeps the objects in memory. This is meant for tests.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: Mutex<HashMap<String, Object>>,
+}
+
+#[derive(Debug)]
+struct Object {
+    contents: Vec<u8>,
+    last_modified: DateTime<Utc>,
+}
+
+impl MemoryStorage {
+    /// An empty storage.
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    /// The contents of the object stored under `key`, if any.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects
+            .lock()
+            .expect("memory storage lock poisoned")
+            .get(key)
+            .map(|object| object.contents.clone())
+    }
+
+    /// The keys of all the stored objects, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .objects
+            .lock()
+            .expect("memory storage lock poisoned")
+            .keys()
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        _options: &'a PutOptions,
+    ) -> BoxFuture<'a, Result<(), failure::Error>> {
+        async move {
+            let contents = std::fs::read(path)?;
+
+            self.objects
+                .lock()
+                .expect("memory storage lock poisoned")
+                .insert(
+                    key.to_owned(),
+                    Object {
+                        contents,
+                        last_modified: Utc::now(),
+                    },
+                );
+
+            Ok(())
+        }
+            .boxed()
+    }
+
+    fn url<'a>(
+        &'a self,
+        key: &'a str,
+        _expires_in: Duration,
+    ) -> BoxFuture<'a, Result<String, failure::Error>> {
+        futures::future::ready(Ok(format!("memory:///{}", key))).boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), failure::Error>> {
+        self.objects
+            .lock()
+            .expect("memory storage lock poisoned")
+            .remove(key);
+
+        futures::future::ready(Ok(())).boxed()
+    }
+
+    fn list<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<StoredObject>, failure::Error>> {
+        let objects = self
+            .objects
+            .lock()
+            .expect("memory storage lock poisoned")
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, object)| StoredObject {
+                key: key.clone(),
+                last_modified: object.last_modified,
+            })
+            .collect();
+
+        futures::future::ready(Ok(objects)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects_can_be_stored_and_deleted() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let path = dir.as_ref().join("out.pdf");
+        std::fs::write(&path, "%PDF").unwrap();
+        let storage = MemoryStorage::new();
+
+        futures::executor::block_on(storage.put(
+            "folder/out.pdf",
+            &path,
+            &PutOptions::for_file("out.pdf", Default::default()),
+        ))
+        .unwrap();
+        assert_eq!(storage.get("folder/out.pdf"), Some(b"%PDF".to_vec()));
+        assert_eq!(storage.keys(), vec!["folder/out.pdf".to_owned()]);
+        let listed = futures::executor::block_on(storage.list("folder/")).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].key, "folder/out.pdf");
+        assert!(futures::executor::block_on(storage.list("other/"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            futures::executor::block_on(storage.url("folder/out.pdf", Duration::from_secs(60)))
+                .unwrap(),
+            "memory:///folder/out.pdf"
+        );
+
+        futures::executor::block_on(storage.delete("folder/out.pdf")).unwrap();
+        assert_eq!(storage.get("folder/out.pdf"), None);
+    }
+}
@@ -0,0 +1,207 @@
+//! Where papers puts the documents it generates and the workspaces it uploads for debugging.
+
+pub(crate) mod credentials;
+mod key_pattern;
+mod local;
+mod memory;
+mod retention;
+mod s3;
+
+pub use self::key_pattern::KeyPattern;
+pub use self::local::LocalStorage;
+pub use self::memory::MemoryStorage;
+pub use self::retention::{collect_garbage, spawn_sweeper, sweep, Retention, Sweep};
+pub use self::s3::S3Storage;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The characters left as is in the segments of the keys, when they are used in URLs.
+const KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// A backend for storing the outputs of the jobs.
+///
+/// Papers ships with [`S3Storage`](S3Storage), [`LocalStorage`](LocalStorage) and
+/// [`MemoryStorage`](MemoryStorage). The backend is selected with [`Config`](crate::Config).
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    /// Store the file at `path` under `key`, replacing any existing object.
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        options: &'a PutOptions,
+    ) -> BoxFuture<'a, Result<(), failure::Error>>;
+
+    /// A URL to download the object stored under `key`, valid for `expires_in`. This may refresh
+    /// credentials, but must not access the object.
+    fn url<'a>(
+        &'a self,
+        key: &'a str,
+        expires_in: Duration,
+    ) -> BoxFuture<'a, Result<String, failure::Error>>;
+
+    /// Delete the object stored under `key`.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), failure::Error>>;
+
+    /// The objects whose keys start with `prefix`, in no particular order.
+    fn list<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<StoredObject>, failure::Error>>;
+
+    /// For the backends whose URLs point to `GET /files/...`: the local path of the object
+    /// stored under `key`, if the `expires` and `signature` query parameters of the URL are
+    /// valid. The other backends do not serve anything.
+    fn signed_path(&self, _key: &str, _expires: i64, _signature: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// An object found in a storage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredObject {
+    /// The key of the object.
+    pub key: String,
+    /// When the object was last written.
+    pub last_modified: DateTime<Utc>,
+}
+
+/// The settings of the stored objects that can be chosen per request, in the `s3` field of the
+/// document and merge specs. Only the S3 storage applies them.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct ObjectSettings {
+    /// The storage class of the objects, like `STANDARD_IA`. Overrides `PAPERS_S3_STORAGE_CLASS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+    /// Tags set on the objects, for lifecycle rules for example. They are added to
+    /// `PAPERS_S3_TAGS`, and take precedence over tags with the same key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// What is stored along with the contents of an object.
+#[derive(Clone, Debug)]
+pub struct PutOptions {
+    /// The `Content-Type` of the object.
+    pub content_type: &'static str,
+    /// The `Content-Disposition` of the object.
+    pub content_disposition: String,
+    /// The settings chosen for the request.
+    pub settings: ObjectSettings,
+}
+
+impl PutOptions {
+    /// The options for a file named `filename`: PDFs are displayed inline by browsers, other
+    /// files are downloaded.
+    pub fn for_file(filename: &str, settings: ObjectSettings) -> Self {
+        PutOptions {
+            content_type: content_type(filename),
+            content_disposition: content_disposition(filename),
+            settings,
+        }
+    }
+}
+
+/// The content type of a file, from the extension of its name.
+pub fn content_type(filename: &str) -> &'static str {
+    match Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("pdf") => "application/pdf",
+        Some("tar") => "application/x-tar",
+        Some("gz") => "application/gzip",
+        Some("zst") => "application/zstd",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The `Content-Disposition` header value for a file, keeping its name when it is downloaded.
+/// Names that are not plain ASCII are also given in the RFC 5987 encoding.
+pub fn content_disposition(filename: &str) -> String {
+    let disposition = if content_type(filename) == "application/pdf" {
+        "inline"
+    } else {
+        "attachment"
+    };
+
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+
+    if fallback == filename {
+        format!("{}; filename=\"{}\"", disposition, filename)
+    } else {
+        format!(
+            "{}; filename=\"{}\"; filename*=UTF-8''{}",
+            disposition,
+            fallback,
+            utf8_percent_encode(filename, NON_ALPHANUMERIC)
+        )
+    }
+}
+
+/// Where an uploaded document ended up.
+#[derive(Debug)]
+pub struct UploadedDocument {
+    /// The key of the document in the storage.
+    pub key: String,
+    /// The URL to download the document.
+    pub url: String,
+    /// When `url` stops working, if it expires.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Percent-encode the segments of a key, to use it as the path of a URL.
+pub fn encode_key(key: &str) -> String {
+    let segments: Vec<String> = key
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, KEY_ENCODE_SET).to_string())
+        .collect();
+
+    segments.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_key_keeps_the_slashes() {
+        assert_eq!(
+            encode_key("2019/10/28/rapport final.pdf"),
+            "2019/10/28/rapport%20final.pdf"
+        );
+    }
+
+    #[test]
+    fn content_disposition_keeps_the_filename() {
+        assert_eq!(
+            content_disposition("report.pdf"),
+            "inline; filename=\"report.pdf\""
+        );
+        assert_eq!(
+            content_disposition("workspace.tar"),
+            "attachment; filename=\"workspace.tar\""
+        );
+        assert_eq!(
+            content_disposition("rapport \"final\" é.pdf"),
+            "inline; filename=\"rapport _final_ _.pdf\"; \
+             filename*=UTF-8''rapport%20%22final%22%20%C3%A9%2Epdf"
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use crate::config::Config;
+use crate::prelude::*;
+use crate::storage::{KeyPattern, Storage, StoredObject};
+use chrono::{DateTime, Datelike, Utc};
+use futures::compat::*;
+use futures::{FutureExt, TryFutureExt};
+use regex::Regex;
+use slog::{error, info, o, warn, Logger};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The keys written before `PAPERS_KEY_PATTERN` existed, in a folder named after the time of the
+/// upload, like `2019-10-28 09:12:03.514625 UTC/out.pdf`.
+const LEGACY_KEY: &str =
+    r"^[0-9]{4}-[0-9]{2}-[0-9]{2} [0-9]{2}:[0-9]{2}:[0-9]{2}(\.[0-9]+)? UTC/[^/]+$";
+
+/// The year of the first release of papers, so of the oldest legacy keys.
+const LEGACY_FIRST_YEAR: i32 = 2017;
+
+/// How long the stored files are kept before the sweeper deletes them. The files are kept
+/// forever when the period is not set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Retention {
+    /// How long the files of the jobs that produced a document are kept.
+    pub success: Option<Duration>,
+    /// How long the files of the jobs that failed, which only left a workspace archive, are
+    /// kept.
+    pub failure: Option<Duration>,
+}
+
+impl Retention {
+    /// Whether any file is ever deleted.
+    pub fn is_enabled(&self) -> bool {
+        self.success.is_some() || self.failure.is_some()
+    }
+}
+
+/// What a sweep found.
+#[derive(Debug, Default)]
+pub struct Sweep {
+    /// The keys of the deleted objects, or of the objects that would be deleted in a dry run.
+    pub deleted: Vec<String>,
+    /// How many objects are kept.
+    pub kept: usize,
+    /// How many objects could not be deleted.
+    pub errors: usize,
+}
+
+/// Delete the objects stored by papers that are older than their retention period. The
+/// objects whose keys match neither the key pattern nor the timestamped folders of the versions
+/// before it are ignored. With `dry_run`, nothing is deleted, but the result lists what would
+/// have been.
+///
+/// A job failed when its folder has no other file than the workspace archive.
+pub async fn sweep(
+    storage: &dyn Storage,
+    key_pattern: &KeyPattern,
+    retention: Retention,
+    dry_run: bool,
+    now: DateTime<Utc>,
+    logger: &Logger,
+) -> Result<Sweep, failure::Error> {
+    let mut objects: Vec<StoredObject> = storage.list(key_pattern.prefix()).await?;
+
+    // The legacy folders are at the root, which the first listing only covers without a prefix.
+    if !key_pattern.prefix().is_empty() {
+        for year in LEGACY_FIRST_YEAR..=now.year() {
+            objects.extend(storage.list(&format!("{}-", year)).await?);
+        }
+    }
+
+    let legacy_key = Regex::new(LEGACY_KEY).expect("legacy key regex is valid");
+    objects.retain(|object| key_pattern.matches(&object.key) || legacy_key.is_match(&object.key));
+    objects.sort_by(|a, b| a.key.cmp(&b.key));
+    objects.dedup_by(|a, b| a.key == b.key);
+
+    let successful_folders: HashSet<&str> = objects
+        .iter()
+        .map(|object| split_key(&object.key))
+        .filter(|(_, filename)| !filename.starts_with("workspace.tar"))
+        .map(|(folder, _)| folder)
+        .collect();
+
+    let mut sweep = Sweep::default();
+
+    for object in &objects {
+        let (folder, _) = split_key(&object.key);
+        let retention = if successful_folders.contains(folder) {
+            retention.success
+        } else {
+            retention.failure
+        };
+
+        let expired = match retention.map(chrono::Duration::from_std).transpose()? {
+            Some(retention) => object.last_modified + retention < now,
+            None => false,
+        };
+
+        if !expired {
+            sweep.kept += 1;
+            continue;
+        }
+
+        if !dry_run {
+            if let Err(err) = storage.delete(&object.key).await {
+                warn!(logger, "Could not delete {:?}: {}", object.key, err);
+                sweep.errors += 1;
+                continue;
+            }
+        }
+
+        sweep.deleted.push(object.key.clone());
+    }
+
+    info!(
+        logger,
+        "{} {} expired objects, kept {}, {} errors.",
+        if dry_run { "Would delete" } else { "Deleted" },
+        sweep.deleted.len(),
+        sweep.kept,
+        sweep.errors
+    );
+
+    Ok(sweep)
+}
+
+/// The folder and the file name of a key.
+fn split_key(key: &str) -> (&str, &str) {
+    match key.rfind('/') {
+        Some(index) => (&key[..index], &key[index + 1..]),
+        None => ("", key),
+    }
+}
+
+/// Run a sweep every `retention_interval` in the background, when a retention period is set.
+/// This must be called on the runtime.
+pub fn spawn_sweeper(config: Arc<Config>) {
+    if !config.retention.is_enabled() {
+        return;
+    }
+
+    let logger = config.logger.new(o!("task" => "retention"));
+
+    tokio::executor::spawn(
+        async move {
+            loop {
+                if let Err(err) = sweep(
+                    config.storage.as_ref(),
+                    &config.key_pattern,
+                    config.retention,
+                    config.retention_dry_run,
+                    Utc::now(),
+                    &logger,
+                )
+                .await
+                {
+                    error!(logger, "Error sweeping the storage: {:?}.", err);
+                }
+
+                tokio::timer::Delay::new(Instant::now() + config.retention_interval)
+                    .compat()
+                    .await
+                    .ok();
+            }
+        }
+            .unit_error()
+            .boxed()
+            .compat(),
+    );
+}
+
+/// Run a single sweep, for the `papers gc` command.
+pub fn collect_garbage(config: Arc<Config>, dry_run: bool) -> Result<Sweep, failure::Error> {
+    if !config.retention.is_enabled() {
+        return Err(format_err!(
+            "Set PAPERS_SUCCESS_RETENTION or PAPERS_FAILURE_RETENTION to collect garbage"
+        ));
+    }
+
+    let logger = config.logger.new(o!("task" => "gc"));
+    let mut runtime = tokio::runtime::Runtime::new()?;
+
+    runtime.block_on(
+        async move {
+            sweep(
+                config.storage.as_ref(),
+                &config.key_pattern,
+                config.retention,
+                dry_run,
+                Utc::now(),
+                &logger,
+            )
+            .await
+        }
+            .boxed()
+            .compat(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, PutOptions};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn storage_with(keys: &[&str]) -> MemoryStorage {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let path = dir.as_ref().join("file");
+        std::fs::write(&path, "contents").unwrap();
+        let storage = MemoryStorage::new();
+
+        for key in keys {
+            futures::executor::block_on(storage.put(
+                key,
+                &path,
+                &PutOptions::for_file("file", Default::default()),
+            ))
+            .unwrap();
+        }
+
+        storage
+    }
+
+    #[test]
+    fn sweep_applies_the_retention_of_successes_and_failures() {
+        let storage = storage_with(&[
+            "2019/10/28/ok/out.pdf",
+            "2019/10/28/ok/workspace.tar",
+            "2019/10/28/failed/workspace.tar.gz",
+            "notes/readme.txt",
+        ]);
+        let retention = Retention {
+            success: Some(30 * DAY),
+            failure: Some(7 * DAY),
+        };
+        let logger = Logger::root(slog::Discard, o!());
+        let in_ten_days = Utc::now() + chrono::Duration::days(10);
+
+        let run = |dry_run| {
+            futures::executor::block_on(sweep(
+                &storage,
+                &KeyPattern::default(),
+                retention,
+                dry_run,
+                in_ten_days,
+                &logger,
+            ))
+            .unwrap()
+        };
+
+        let dry_run = run(true);
+        assert_eq!(dry_run.deleted, vec!["2019/10/28/failed/workspace.tar.gz"]);
+        assert_eq!(dry_run.kept, 2);
+        assert_eq!(storage.keys().len(), 4);
+
+        let deleted = run(false);
+        assert_eq!(deleted.deleted, dry_run.deleted);
+        assert_eq!(
+            storage.keys(),
+            vec![
+                "2019/10/28/ok/out.pdf",
+                "2019/10/28/ok/workspace.tar",
+                "notes/readme.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn sweep_deletes_the_files_stored_before_the_key_pattern() {
+        let storage = storage_with(&[
+            "2019-10-28 09:12:03.514625 UTC/out.pdf",
+            "2019-10-28 09:12:03.514625 UTC/workspace.tar",
+            "2019-10-29 10:00:00 UTC/workspace.tar",
+            "2019-10-30/notes.txt",
+            "tenants/acme/abc/out.pdf",
+        ]);
+        let key_pattern = KeyPattern::parse("tenants/{tenant}/{job_id}/{filename}").unwrap();
+        let retention = Retention {
+            success: Some(DAY),
+            failure: None,
+        };
+        let logger = Logger::root(slog::Discard, o!());
+        let in_ten_days = Utc::now() + chrono::Duration::days(10);
+
+        let sweep = futures::executor::block_on(sweep(
+            &storage,
+            &key_pattern,
+            retention,
+            false,
+            in_ten_days,
+            &logger,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            sweep.deleted,
+            vec![
+                "2019-10-28 09:12:03.514625 UTC/out.pdf",
+                "2019-10-28 09:12:03.514625 UTC/workspace.tar",
+                "tenants/acme/abc/out.pdf",
+            ]
+        );
+        assert_eq!(sweep.kept, 1);
+        assert_eq!(
+            storage.keys(),
+            vec![
+                "2019-10-29 10:00:00 UTC/workspace.tar",
+                "2019-10-30/notes.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn sweep_keeps_everything_without_retention() {
+        let storage = storage_with(&["2019/10/28/failed/workspace.tar"]);
+        let logger = Logger::root(slog::Discard, o!());
+        let in_a_year = Utc::now() + chrono::Duration::days(365);
+
+        let sweep = futures::executor::block_on(sweep(
+            &storage,
+            &KeyPattern::default(),
+            Retention::default(),
+            false,
+            in_a_year,
+            &logger,
+        ))
+        .unwrap();
+
+        assert!(sweep.deleted.is_empty());
+        assert_eq!(storage.keys().len(), 1);
+    }
+}
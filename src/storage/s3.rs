@@ -0,0 +1,413 @@
+use crate::config::{S3Config, ServerSideEncryption};
+use crate::prelude::*;
+use crate::storage::{PutOptions, Storage, StoredObject};
+use chrono::{DateTime, Utc};
+use futures::compat::*;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rusoto_s3::S3;
+use slog::{debug, warn, Logger};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Files larger than this are uploaded with a multipart upload.
+const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// The size of the parts of a multipart upload. Only one part is held in memory at a time.
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Stores the objects in an S3 bucket, and hands out presigned URLs.
+#[derive(Debug)]
+pub struct S3Storage {
+    config: S3Config,
+    logger: Logger,
+}
+
+impl S3Storage {
+    /// A storage using the bucket and credentials from `config`.
+    pub fn new(config: S3Config, logger: Logger) -> Self {
+        S3Storage { config, logger }
+    }
+}
+
+/// The headers of an object, common to `PutObject` and `CreateMultipartUpload`.
+struct ObjectHeaders {
+    content_disposition: Option<String>,
+    content_type: Option<String>,
+    server_side_encryption: Option<String>,
+    ssekms_key_id: Option<String>,
+    storage_class: Option<String>,
+    tagging: Option<String>,
+}
+
+impl S3Storage {
+    /// Combine the configured defaults with the options of the upload.
+    fn headers(&self, options: &PutOptions) -> ObjectHeaders {
+        let (server_side_encryption, ssekms_key_id) = match &self.config.server_side_encryption {
+            None => (None, None),
+            Some(ServerSideEncryption::S3) => (Some("AES256".to_owned()), None),
+            Some(ServerSideEncryption::Kms { key_id }) => {
+                (Some("aws:kms".to_owned()), key_id.clone())
+            }
+        };
+
+        let mut tags = self.config.tags.clone();
+        tags.extend(options.settings.tags.clone());
+
+        ObjectHeaders {
+            content_disposition: Some(options.content_disposition.clone()),
+            content_type: Some(options.content_type.to_owned()),
+            server_side_encryption,
+            ssekms_key_id,
+            storage_class: options
+                .settings
+                .storage_class
+                .clone()
+                .or_else(|| self.config.storage_class.clone()),
+            tagging: tagging(&tags),
+        }
+    }
+
+    /// Streams a small file to the bucket with a single `PutObject` request.
+    async fn put_single(
+        &self,
+        key: &str,
+        path: &Path,
+        size: u64,
+        headers: ObjectHeaders,
+    ) -> Result<(), failure::Error> {
+        use futures01::Stream;
+        use tokio::codec::{BytesCodec, FramedRead};
+
+        let file = tokio::fs::File::open(path.to_owned()).compat().await?;
+        let body = FramedRead::new(file, BytesCodec::new()).map(|bytes| bytes.freeze());
+
+        let request = rusoto_s3::PutObjectRequest {
+            body: Some(rusoto_core::ByteStream::new(body)),
+            bucket: self.config.bucket.clone(),
+            content_disposition: headers.content_disposition,
+            content_length: Some(size as i64),
+            content_type: headers.content_type,
+            key: key.to_owned(),
+            server_side_encryption: headers.server_side_encryption,
+            ssekms_key_id: headers.ssekms_key_id,
+            storage_class: headers.storage_class,
+            tagging: headers.tagging,
+            ..Default::default()
+        };
+
+        self.config
+            .client()
+            .put_object(request)
+            .compat()
+            .await
+            .context("Error during S3 upload")?;
+
+        Ok(())
+    }
+
+    /// Uploads a large file with a multipart upload, reading one part at a time. The upload is
+    /// aborted if any part fails, so no incomplete parts are left in the bucket.
+    async fn put_multipart(
+        &self,
+        key: &str,
+        path: &Path,
+        size: u64,
+        headers: ObjectHeaders,
+    ) -> Result<(), failure::Error> {
+        let client = self.config.client();
+
+        let request = rusoto_s3::CreateMultipartUploadRequest {
+            bucket: self.config.bucket.clone(),
+            content_disposition: headers.content_disposition,
+            content_type: headers.content_type,
+            key: key.to_owned(),
+            server_side_encryption: headers.server_side_encryption,
+            ssekms_key_id: headers.ssekms_key_id,
+            storage_class: headers.storage_class,
+            tagging: headers.tagging,
+            ..Default::default()
+        };
+        let upload_id = client
+            .create_multipart_upload(request)
+            .compat()
+            .await
+            .context("Error creating the S3 multipart upload")?
+            .upload_id
+            .ok_or_else(|| format_err!("S3 returned no multipart upload id"))?;
+
+        match self
+            .upload_parts(&client, key, path, size, &upload_id)
+            .await
+        {
+            Ok(parts) => {
+                let request = rusoto_s3::CompleteMultipartUploadRequest {
+                    bucket: self.config.bucket.clone(),
+                    key: key.to_owned(),
+                    multipart_upload: Some(rusoto_s3::CompletedMultipartUpload {
+                        parts: Some(parts),
+                    }),
+                    upload_id,
+                    ..Default::default()
+                };
+
+                client
+                    .complete_multipart_upload(request)
+                    .compat()
+                    .await
+                    .context("Error completing the S3 multipart upload")?;
+
+                Ok(())
+            }
+            Err(err) => {
+                let request = rusoto_s3::AbortMultipartUploadRequest {
+                    bucket: self.config.bucket.clone(),
+                    key: key.to_owned(),
+                    upload_id,
+                    ..Default::default()
+                };
+
+                if let Err(abort_err) = client.abort_multipart_upload(request).compat().await {
+                    warn!(
+                        self.logger,
+                        "Could not abort the multipart upload of {:?}: {}", key, abort_err
+                    );
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    async fn upload_parts(
+        &self,
+        client: &rusoto_s3::S3Client,
+        key: &str,
+        path: &Path,
+        size: u64,
+        upload_id: &str,
+    ) -> Result<Vec<rusoto_s3::CompletedPart>, failure::Error> {
+        let mut file = tokio::fs::File::open(path.to_owned()).compat().await?;
+        let mut parts = Vec::new();
+
+        for (index, part_size) in part_sizes(size, PART_SIZE).into_iter().enumerate() {
+            let part_number = index as i64 + 1;
+            let (returned_file, buffer) = tokio::io::read_exact(file, vec![0; part_size as usize])
+                .compat()
+                .await?;
+            file = returned_file;
+
+            debug!(
+                self.logger,
+                "Uploading part {} ({} bytes) of {:?}.", part_number, part_size, key
+            );
+            let request = rusoto_s3::UploadPartRequest {
+                body: Some(buffer.into()),
+                bucket: self.config.bucket.clone(),
+                content_length: Some(part_size as i64),
+                key: key.to_owned(),
+                part_number,
+                upload_id: upload_id.to_owned(),
+                ..Default::default()
+            };
+
+            let output = client
+                .upload_part(request)
+                .compat()
+                .await
+                .with_context(|_| format!("Error uploading part {} to S3", part_number))?;
+
+            parts.push(rusoto_s3::CompletedPart {
+                e_tag: output.e_tag,
+                part_number: Some(part_number),
+            });
+        }
+
+        Ok(parts)
+    }
+}
+
+/// Encode the tags for the `x-amz-tagging` header.
+fn tagging(tags: &BTreeMap<String, String>) -> Option<String> {
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+    if tags.is_empty() {
+        return None;
+    }
+
+    let pairs: Vec<String> = tags
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(key, NON_ALPHANUMERIC),
+                utf8_percent_encode(value, NON_ALPHANUMERIC)
+            )
+        })
+        .collect();
+
+    Some(pairs.join("&"))
+}
+
+/// The sizes of the parts a file of `size` bytes is split into for a multipart upload.
+fn part_sizes(size: u64, part_size: u64) -> Vec<u64> {
+    let mut sizes = vec![part_size; (size / part_size) as usize];
+
+    let remainder = size % part_size;
+    if remainder > 0 {
+        sizes.push(remainder);
+    }
+
+    sizes
+}
+
+impl Storage for S3Storage {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        options: &'a PutOptions,
+    ) -> BoxFuture<'a, Result<(), failure::Error>> {
+        async move {
+            let size = tokio::fs::metadata(path.to_owned()).compat().await?.len();
+
+            debug!(
+                self.logger,
+                "Uploading {:?} ({} bytes) to {:?} / {:?}.", path, size, self.config.bucket, key
+            );
+
+            let headers = self.headers(options);
+
+            if size > MULTIPART_THRESHOLD {
+                self.put_multipart(key, path, size, headers).await
+            } else {
+                self.put_single(key, path, size, headers).await
+            }
+        }
+            .boxed()
+    }
+
+    fn url<'a>(
+        &'a self,
+        key: &'a str,
+        expires_in: Duration,
+    ) -> BoxFuture<'a, Result<String, failure::Error>> {
+        use rusoto_credential::ProvideAwsCredentials;
+        use rusoto_s3::util::*;
+        use rusoto_s3::GetObjectRequest;
+
+        async move {
+            // Temporary credentials are refreshed here when they are about to expire.
+            let credentials = self
+                .config
+                .credentials_provider()
+                .credentials()
+                .compat()
+                .await
+                .context("Error getting the AWS credentials")?;
+
+            let request = GetObjectRequest {
+                bucket: self.config.bucket.clone(),
+                key: key.to_owned(),
+                ..Default::default()
+            };
+
+            let options = PreSignedRequestOption { expires_in };
+
+            Ok(request.get_presigned_url(&self.config.region, &credentials, &options))
+        }
+            .boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), failure::Error>> {
+        async move {
+            let request = rusoto_s3::DeleteObjectRequest {
+                bucket: self.config.bucket.clone(),
+                key: key.to_owned(),
+                ..Default::default()
+            };
+
+            self.config
+                .client()
+                .delete_object(request)
+                .compat()
+                .await
+                .context("Error during S3 deletion")?;
+
+            Ok(())
+        }
+            .boxed()
+    }
+
+    fn list<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<StoredObject>, failure::Error>> {
+        async move {
+            let client = self.config.client();
+            let mut objects = Vec::new();
+            let mut continuation_token = None;
+
+            loop {
+                let request = rusoto_s3::ListObjectsV2Request {
+                    bucket: self.config.bucket.clone(),
+                    prefix: Some(prefix.to_owned()),
+                    continuation_token: continuation_token.take(),
+                    ..Default::default()
+                };
+
+                let response = client
+                    .list_objects_v2(request)
+                    .compat()
+                    .await
+                    .context("Error listing the S3 objects")?;
+
+                for object in response.contents.unwrap_or_default() {
+                    if let (Some(key), Some(last_modified)) = (object.key, object.last_modified) {
+                        objects.push(StoredObject {
+                            key,
+                            last_modified: DateTime::parse_from_rfc3339(&last_modified)?
+                                .with_timezone(&Utc),
+                        });
+                    }
+                }
+
+                match response.next_continuation_token {
+                    Some(token) if response.is_truncated == Some(true) => {
+                        continuation_token = Some(token)
+                    }
+                    _ => break,
+                }
+            }
+
+            Ok(objects)
+        }
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_sizes_cover_the_whole_file() {
+        assert_eq!(part_sizes(0, 8), Vec::<u64>::new());
+        assert_eq!(part_sizes(8, 8), vec![8]);
+        assert_eq!(part_sizes(20, 8), vec![8, 8, 4]);
+    }
+
+    #[test]
+    fn tagging_encodes_the_tags() {
+        let mut tags = BTreeMap::new();
+        assert_eq!(tagging(&tags), None);
+
+        tags.insert("team".to_owned(), "billing".to_owned());
+        tags.insert("retention".to_owned(), "30 days".to_owned());
+        assert_eq!(
+            tagging(&tags),
+            Some("retention=30%20days&team=billing".to_owned())
+        );
+    }
+}
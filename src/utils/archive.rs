@@ -0,0 +1,217 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use slog::{debug, Logger};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// How the workspace archives are compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// A plain tar file.
+    None,
+    /// A gzipped tar file.
+    Gzip,
+    /// A tar file compressed with Zstandard.
+    Zstd,
+}
+
+impl Compression {
+    /// The name of the archive, with the extension matching the compression.
+    pub fn archive_name(self) -> &'static str {
+        match self {
+            Compression::None => "workspace.tar",
+            Compression::Gzip => "workspace.tar.gz",
+            Compression::Zstd => "workspace.tar.zst",
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = failure::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            other => Err(format_err!(
+                "Unknown compression {:?}, expected none, gzip or zstd",
+                other
+            )),
+        }
+    }
+}
+
+/// Tar the contents of the workspace (the temporary directory where we generated the PDF) into a
+/// temporary file, which is deleted when the returned value is dropped. The files in `exclude`
+/// are left out.
+///
+/// The archive is written to disk as it is built, so memory use does not depend on the size of
+/// the workspace. Reading, compressing and writing the files blocks, so it happens on a thread
+/// of its own rather than on the executor.
+pub async fn tar_workspace(
+    logger: Logger,
+    workspace: PathBuf,
+    exclude: Vec<PathBuf>,
+    compression: Compression,
+) -> Result<mktemp::Temp, failure::Error> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+
+    std::thread::Builder::new()
+        .name("papers-tar".to_owned())
+        .spawn(move || {
+            sender
+                .send(write_archive(logger, &workspace, &exclude, compression))
+                .ok();
+        })?;
+
+    receiver
+        .await
+        .map_err(|_| format_err!("The thread archiving the workspace panicked"))?
+}
+
+fn write_archive(
+    logger: Logger,
+    workspace: &Path,
+    exclude: &[PathBuf],
+    compression: Compression,
+) -> Result<mktemp::Temp, failure::Error> {
+    let dir_name: PathBuf = workspace.components().last().unwrap().as_os_str().into();
+    debug!(
+        logger,
+        "Tarring {:?} as {:?} ({:?}).", &workspace, &dir_name, compression
+    );
+
+    let tar_file = mktemp::Temp::new_file()?;
+    let output_file = BufWriter::new(std::fs::File::create(&tar_file)?);
+
+    match compression {
+        Compression::None => {
+            let mut tarrer = tar::Builder::new(output_file);
+            append_dir(&mut tarrer, workspace, &dir_name, exclude)?;
+            tarrer.into_inner()?.flush()?;
+        }
+        Compression::Gzip => {
+            let encoder =
+                flate2::write::GzEncoder::new(output_file, flate2::Compression::default());
+            let mut tarrer = tar::Builder::new(encoder);
+            append_dir(&mut tarrer, workspace, &dir_name, exclude)?;
+            tarrer.into_inner()?.finish()?.flush()?;
+        }
+        Compression::Zstd => {
+            let encoder = zstd::Encoder::new(output_file, 0)?;
+            let mut tarrer = tar::Builder::new(encoder);
+            append_dir(&mut tarrer, workspace, &dir_name, exclude)?;
+            tarrer.into_inner()?.finish()?.flush()?;
+        }
+    }
+
+    debug!(logger, "Tar was successful.");
+
+    Ok(tar_file)
+}
+
+/// Recursively add the contents of `dir` to the archive under `name`, except for the paths in
+/// `exclude`.
+fn append_dir<W: Write>(
+    tarrer: &mut tar::Builder<W>,
+    dir: &Path,
+    name: &Path,
+    exclude: &[PathBuf],
+) -> Result<(), failure::Error> {
+    tarrer.append_dir(name, dir)?;
+
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let entry_name = name.join(entry.file_name());
+
+        if exclude.contains(&path) {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            append_dir(tarrer, &path, &entry_name, exclude)?;
+        } else {
+            tarrer.append_path_with_name(&path, &entry_name)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn archived_paths(archive: impl Read) -> Vec<PathBuf> {
+        tar::Archive::new(archive)
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn tar_workspace_archives_the_whole_directory() {
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let workspace = mktemp::Temp::new_dir().unwrap();
+        std::fs::write(workspace.as_ref().join("out.tex"), "hello").unwrap();
+
+        let tar_file = futures::executor::block_on(tar_workspace(
+            logger,
+            workspace.to_path_buf(),
+            Vec::new(),
+            Compression::None,
+        ))
+        .unwrap();
+
+        let dir_name = workspace.as_ref().file_name().unwrap().to_owned();
+        let paths = archived_paths(std::fs::File::open(&tar_file).unwrap());
+
+        assert!(paths.contains(&PathBuf::from(&dir_name).join("out.tex")));
+        assert!(!paths.iter().any(|path| path.ends_with("workspace.tar")));
+    }
+
+    #[test]
+    fn tar_workspace_compresses_and_excludes_files() {
+        let workspace = mktemp::Temp::new_dir().unwrap();
+        let asset = workspace.as_ref().join("logo.png");
+        std::fs::write(workspace.as_ref().join("out.tex"), "hello").unwrap();
+        std::fs::write(&asset, "png").unwrap();
+
+        let dir_name = PathBuf::from(workspace.as_ref().file_name().unwrap());
+        let expected = vec![dir_name.clone(), dir_name.join("out.tex")];
+        let exclude = vec![asset];
+
+        for &compression in &[Compression::Gzip, Compression::Zstd] {
+            let logger = Logger::root(slog::Discard, slog::o!());
+            let tar_file = futures::executor::block_on(tar_workspace(
+                logger,
+                workspace.to_path_buf(),
+                exclude.clone(),
+                compression,
+            ))
+            .unwrap();
+            let file = std::fs::File::open(&tar_file).unwrap();
+
+            let paths = match compression {
+                Compression::Gzip => archived_paths(flate2::read::GzDecoder::new(file)),
+                _ => archived_paths(zstd::Decoder::new(file).unwrap()),
+            };
+
+            assert_eq!(paths, expected);
+        }
+    }
+
+    #[test]
+    fn compression_is_parsed() {
+        assert_eq!("gzip".parse::<Compression>().unwrap(), Compression::Gzip);
+        assert_eq!(Compression::Zstd.archive_name(), "workspace.tar.zst");
+        assert!("brotli".parse::<Compression>().is_err());
+    }
+}
@@ -1,29 +1,146 @@
 use crate::papers::Summary;
 use crate::prelude::*;
+use chrono::Utc;
 use futures::compat::*;
+use futures::{FutureExt, TryFutureExt};
+use rand::Rng;
 use reqwest::r#async::Client;
 use sentry;
-use slog::{debug, error, info, Logger};
+use serde_json::json;
+use slog::{debug, error, info, warn, Logger};
+use std::time::{Duration, Instant};
+
+/// The longest we wait between two attempts at posting to a callback URL.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
 
 /// This reports to the provided callback url with the presigned URL of the generated PDF and the
-/// location of the debugging output. It returns the response from the callback url as a future.
-pub async fn report_success(
+/// location of the debugging output, as described by `summary`. See
+/// [`deliver`](deliver) for how it is delivered.
+pub fn report_success(config: Arc<Config>, logger: Logger, callback_url: String, summary: Summary) {
+    deliver(config, logger, callback_url, summary);
+}
+
+/// Tell the callback URL that the job was cancelled on request.
+pub fn report_cancellation(
+    config: Arc<Config>,
+    logger: Logger,
+    callback_url: String,
+    summary: Summary,
+) {
+    deliver(config, logger, callback_url, summary);
+}
+
+/// When an error occurs during the generation process, it is reported with this function. It calls
+/// the `callback_url` from the document spec, posting the `summary` with the error and the key
+/// where the debug output can be found.
+pub fn report_failure(
+    config: Arc<Config>,
+    logger: Logger,
+    error: &failure::Error,
+    summary: Summary,
+    callback_url: String,
+) {
+    // For the logs and sentry, we want the most detailed (but less readable) version of the
+    // error, so we use the [`Debug`](std::fmt::Debug) implementation.
+    error!(logger, "Error to be reported to the callback URL: {:?}.", error);
+    sentry::capture_message(&format!("{:?}", error), sentry::Level::Error);
+
+    deliver(config, logger, callback_url, summary);
+}
+
+/// Post `summary` to the callback URL in a task of its own, so the retries do not hold the
+/// worker of the job, and the next queued jobs start right away. This must be called on the
+/// runtime.
+fn deliver(config: Arc<Config>, logger: Logger, callback_url: String, summary: Summary) {
+    tokio::executor::spawn(
+        async move {
+            post_summary(&config, logger.clone(), &callback_url, &summary)
+                .await
+                .map_err(|err| error!(logger, "Error posting to the callback URL: {:?}.", err))
+                .ok();
+        }
+            .unit_error()
+            .boxed()
+            .compat(),
+    );
+}
+
+/// Post `summary` as JSON to the callback URL, retrying with an exponential backoff up to
+/// `callback_max_attempts` times. When all attempts fail, the summary is recorded as a dead
+/// letter.
+async fn post_summary(
+    config: &Config,
     logger: Logger,
     callback_url: &str,
-    s3_prefix: String,
-    presigned_url: String,
+    summary: &Summary,
 ) -> Result<(), failure::Error> {
     let client = Client::new();
-    let outcome = Summary::File {
-        file: presigned_url,
-        s3_folder: s3_prefix,
-    };
 
-    debug!(logger, "Summary sent to callback: {:?}", outcome);
+    debug!(logger, "Summary sent to callback: {:?}.", summary);
+
+    let mut attempt = 1;
+
+    loop {
+        let err = match post_summary_once(config, &client, &logger, callback_url, summary).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+        if attempt >= config.callback_max_attempts {
+            dead_letter(config, &logger, callback_url, summary, &err);
+            return Err(err);
+        }
+
+        let delay = backoff_delay(
+            config.callback_backoff,
+            attempt,
+            rand::thread_rng().gen(),
+        );
+
+        warn!(
+            logger,
+            "Attempt {} at posting to the callback URL failed, retrying in {:?}: {}",
+            attempt,
+            delay,
+            display_error(&err),
+        );
+
+        tokio::timer::Delay::new(Instant::now() + delay)
+            .compat()
+            .await?;
+
+        attempt += 1;
+    }
+}
+
+/// A single attempt at posting `summary` to the callback URL. Responses with a status other than
+/// 2xx count as failures.
+///
+/// When `PAPERS_CALLBACK_SECRET` is set, the request is signed (see
+/// [`signing::sign`](crate::utils::signing::sign)).
+async fn post_summary_once(
+    config: &Config,
+    client: &Client,
+    logger: &Logger,
+    callback_url: &str,
+    summary: &Summary,
+) -> Result<(), failure::Error> {
+    use crate::utils::signing::{sign, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+    let body = serde_json::to_vec(summary)?;
+    let mut request = client
+        .post(callback_url)
+        .header(http::header::CONTENT_TYPE, "application/json");
 
-    let callback_response = client
-        .post(&callback_url.to_string())
-        .json(&outcome)
+    if let Some(secret) = &config.callback_secret {
+        let timestamp = Utc::now().timestamp();
+        request = request
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(secret, timestamp, &body));
+    }
+
+    let callback_response = request
+        .body(body)
         .send()
         .compat()
         .await
@@ -43,42 +160,89 @@ pub async fn report_success(
         "Callback response body: {:?}.",
         callback_response.body(),
     );
+
+    if !callback_response.status().is_success() {
+        return Err(format_err!(
+            "The callback URL responded with {}",
+            callback_response.status()
+        ));
+    }
+
     Ok(())
 }
 
-/// When an error occurs during the generation process, it is reported with this function. It calls
-/// the `callback_url` from the document spec, posting a `Summary` object with the error and the
-/// key where the debug output can be found.
-pub async fn report_failure(
-    logger: Logger,
-    error: failure::Error,
-    s3_prefix: String,
+/// The delay before the next attempt: it doubles after each attempt, up to `MAX_BACKOFF`. Half of
+/// it is randomized with `jitter` (between 0 and 1), so callbacks failing at the same time do not
+/// all hit the callback URL again at the same time.
+fn backoff_delay(base: Duration, attempt: u32, jitter: f64) -> Duration {
+    let exponential = base
+        .checked_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF);
+    let half = exponential / 2;
+
+    half + half.mul_f64(jitter)
+}
+
+/// Give up on a callback. The summary is logged and reported to sentry, and when
+/// `PAPERS_DATA_DIR` is set, it is also written to its `dead_letters` directory, so it can be
+/// delivered by hand.
+fn dead_letter(
+    config: &Config,
+    logger: &Logger,
     callback_url: &str,
-) -> Result<(), failure::Error> {
-    let client = Client::new();
+    summary: &Summary,
+    error: &failure::Error,
+) {
+    error!(
+        logger,
+        "Giving up on posting to the callback URL {} after {} attempts: {:?}. Summary: {:?}.",
+        callback_url,
+        config.callback_max_attempts,
+        error,
+        summary,
+    );
+    sentry::capture_message(
+        &format!("Callback to {} failed: {:?}", callback_url, error),
+        sentry::Level::Error,
+    );
 
-    // For the logs and sentry, we want the most detailed (but less readable) version of the
-    // error, so we use the [`Debug`](std::fmt::Debug) implementation.
-    error!(logger, "Error to be reported to the callback URL: {:?}.", &error);
-    sentry::capture_message(&format!("{:?}", &error), sentry::Level::Error);
+    if let Some(data_dir) = &config.data_dir {
+        let record = json!({
+            "callback_url": callback_url,
+            "summary": summary,
+            "error": display_error(error),
+            "failed_at": Utc::now(),
+        });
+        let dead_letters_dir = data_dir.join("dead_letters");
+        let path = dead_letters_dir.join(format!("{}.json", uuid::Uuid::new_v4()));
 
-    // For the callback, we want the user-facing version of the error.
-    let err_msg = display_error(&error);
+        std::fs::create_dir_all(&dead_letters_dir)
+            .and_then(|()| std::fs::write(&path, record.to_string()))
+            .map_err(|err| error!(logger, "Could not write dead letter {:?}: {:?}.", path, err))
+            .ok();
+    }
+}
 
-    let outcome = Summary::Error {
-        backtrace: error.backtrace().to_string(),
-        error: err_msg,
-        s3_folder: s3_prefix,
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    debug!(logger, "Summary sent to callback: {:?}.", outcome);
+    #[test]
+    fn backoff_delay_doubles_after_each_attempt() {
+        let base = Duration::from_secs(2);
 
-    client
-        .post(callback_url)
-        .json(&outcome)
-        .send()
-        .compat()
-        .await?;
+        assert_eq!(backoff_delay(base, 1, 0.0), Duration::from_secs(1));
+        assert_eq!(backoff_delay(base, 2, 0.0), Duration::from_secs(2));
+        assert_eq!(backoff_delay(base, 3, 0.0), Duration::from_secs(4));
+        assert_eq!(backoff_delay(base, 3, 0.5), Duration::from_secs(6));
+    }
 
-    Ok(())
+    #[test]
+    fn backoff_delay_is_capped() {
+        let base = Duration::from_secs(2);
+
+        assert_eq!(backoff_delay(base, 12, 1.0), MAX_BACKOFF);
+        assert_eq!(backoff_delay(base, 100, 1.0), MAX_BACKOFF);
+    }
 }
@@ -0,0 +1,229 @@
+use crate::prelude::*;
+use crate::utils::process::{find_program, output_with_limits};
+use crate::utils::sandbox;
+use slog::{debug, Logger};
+use std::ffi::OsString;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Who can open the workspace archives, which contain the variables and assets of the jobs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WorkspaceEncryption {
+    /// Encrypted with `age` for these recipients, `age1...` or SSH public keys.
+    Age {
+        /// The public keys of the recipients.
+        recipients: Vec<String>,
+    },
+    /// Encrypted with `gpg` for an OpenPGP public key.
+    OpenPgp {
+        /// The file with the public key, armored or not.
+        key_file: PathBuf,
+    },
+}
+
+impl WorkspaceEncryption {
+    /// Read `PAPERS_WORKSPACE_AGE_RECIPIENTS` or `PAPERS_WORKSPACE_PGP_KEY_FILE`. The archives
+    /// are not encrypted when neither is set.
+    ///
+    /// It fails when the program that encrypts cannot be found, rather than at the first upload.
+    pub fn from_env() -> Result<Option<Self>, failure::Error> {
+        let encryption = WorkspaceEncryption::from_env_vars()?;
+
+        if let Some(encryption) = &encryption {
+            let program = encryption.program();
+            if find_program(Path::new(program)).is_none() {
+                return Err(format_err!(
+                    "{} is needed to encrypt the workspace archives, but it is not in the PATH",
+                    program
+                ));
+            }
+        }
+
+        Ok(encryption)
+    }
+
+    fn from_env_vars() -> Result<Option<Self>, failure::Error> {
+        let recipients = std::env::var("PAPERS_WORKSPACE_AGE_RECIPIENTS").ok();
+        let key_file = std::env::var("PAPERS_WORKSPACE_PGP_KEY_FILE").ok();
+
+        match (recipients, key_file) {
+            (Some(_), Some(_)) => Err(format_err!(
+                "Only one of PAPERS_WORKSPACE_AGE_RECIPIENTS and PAPERS_WORKSPACE_PGP_KEY_FILE \
+                 can be set"
+            )),
+            (Some(recipients), None) => Ok(Some(WorkspaceEncryption::age(&recipients)?)),
+            (None, Some(key_file)) => {
+                let key_file = PathBuf::from(key_file);
+                if !key_file.is_file() {
+                    return Err(format_err!(
+                        "PAPERS_WORKSPACE_PGP_KEY_FILE {:?} is not a file",
+                        key_file
+                    ));
+                }
+                // The sandbox mounts the key file at the same, absolute, path.
+                let key_file = key_file.canonicalize()?;
+
+                Ok(Some(WorkspaceEncryption::OpenPgp { key_file }))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Encryption with `age` for a comma-separated list of recipients.
+    fn age(recipients: &str) -> Result<Self, failure::Error> {
+        let recipients: Vec<String> = recipients
+            .split(',')
+            .map(str::trim)
+            .filter(|recipient| !recipient.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        if recipients.is_empty() {
+            return Err(format_err!("PAPERS_WORKSPACE_AGE_RECIPIENTS is empty"));
+        }
+
+        Ok(WorkspaceEncryption::Age { recipients })
+    }
+
+    /// The program that encrypts.
+    fn program(&self) -> &'static str {
+        match self {
+            WorkspaceEncryption::Age { .. } => "age",
+            WorkspaceEncryption::OpenPgp { .. } => "gpg",
+        }
+    }
+
+    /// The extension added to the name of the encrypted archives.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            WorkspaceEncryption::Age { .. } => "age",
+            WorkspaceEncryption::OpenPgp { .. } => "gpg",
+        }
+    }
+
+    /// Encrypt a file into a temporary file, which is deleted when the returned value is
+    /// dropped. The program runs with the sandbox, the resource limits and the encryption
+    /// timeout of `config`.
+    pub async fn encrypt(
+        &self,
+        logger: &Logger,
+        config: &Config,
+        input: &Path,
+    ) -> Result<mktemp::Temp, failure::Error> {
+        // The program only writes to this directory: the output, and the home directory gpg
+        // needs, which is kept away from any keyring of the host.
+        let work_dir = mktemp::Temp::new_dir()?;
+        let encrypted = work_dir.as_ref().join("encrypted");
+        let gpg_home = work_dir.as_ref().join("gnupg");
+        std::fs::create_dir(&gpg_home)?;
+        std::fs::set_permissions(&gpg_home, std::fs::Permissions::from_mode(0o700))?;
+
+        let program = self.program();
+        let inputs = match self {
+            WorkspaceEncryption::Age { .. } => vec![input],
+            WorkspaceEncryption::OpenPgp { key_file } => vec![input, key_file.as_path()],
+        };
+        debug!(logger, "Encrypting {:?} with {}.", input, program);
+
+        let mut command =
+            sandbox::command_reading(config.sandbox.as_ref(), program, work_dir.as_ref(), &inputs);
+        command.args(self.args(input, &encrypted, &gpg_home));
+        let result = output_with_limits(
+            command,
+            program,
+            config.encryption_timeout,
+            config.resource_limits,
+        )
+        .await?;
+
+        if !result.status.success() {
+            return Err(format_err!(
+                "Encrypting the workspace failed. {} output:\n{}",
+                program,
+                crate::utils::process::whole_output(&result)?
+            ));
+        }
+
+        // Out of the directory, which is deleted with the home directory of gpg.
+        let output = mktemp::Temp::new_path();
+        std::fs::rename(&encrypted, &output)?;
+
+        Ok(output)
+    }
+
+    fn args(&self, input: &Path, output: &Path, gpg_home: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+
+        match self {
+            WorkspaceEncryption::Age { recipients } => {
+                for recipient in recipients {
+                    args.push("--recipient".into());
+                    args.push(recipient.into());
+                }
+                args.push("--output".into());
+                args.push(output.into());
+            }
+            WorkspaceEncryption::OpenPgp { key_file } => {
+                args.extend(
+                    ["--batch", "--yes", "--no-tty", "--trust-model", "always"]
+                        .iter()
+                        .map(OsString::from),
+                );
+                args.push("--homedir".into());
+                args.push(gpg_home.into());
+                args.push("--recipient-file".into());
+                args.push(key_file.into());
+                args.push("--output".into());
+                args.push(output.into());
+                args.push("--encrypt".into());
+            }
+        }
+
+        args.push(input.into());
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn age_recipients_are_parsed() {
+        assert_eq!(
+            WorkspaceEncryption::age("age1abc, age1def,").unwrap(),
+            WorkspaceEncryption::Age {
+                recipients: vec!["age1abc".to_owned(), "age1def".to_owned()]
+            }
+        );
+        assert!(WorkspaceEncryption::age(" , ").is_err());
+    }
+
+    #[test]
+    fn args_name_the_recipients() {
+        let age = WorkspaceEncryption::Age {
+            recipients: vec!["age1abc".to_owned(), "age1def".to_owned()],
+        };
+        assert_eq!(
+            age.args(Path::new("in.tar"), Path::new("out"), Path::new("home")),
+            vec![
+                "--recipient",
+                "age1abc",
+                "--recipient",
+                "age1def",
+                "--output",
+                "out",
+                "in.tar"
+            ]
+        );
+
+        let pgp = WorkspaceEncryption::OpenPgp {
+            key_file: PathBuf::from("oncall.asc"),
+        };
+        let args = pgp.args(Path::new("in.tar"), Path::new("out"), Path::new("home"));
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--recipient-file", "oncall.asc"]));
+        assert_eq!(args.last().unwrap(), "in.tar");
+    }
+}
@@ -1,12 +1,20 @@
+/// Archives of the workspaces.
+pub mod archive;
 /// Utility-functions for the callbacks of asynchronous jobs.
 pub mod callbacks;
+/// Encryption of the workspace archives.
+pub mod encryption;
 /// HTTP client related utilities.
 pub mod http;
 /// Logging utilities.
 pub mod logging;
+/// Inspection of the generated PDFs.
+pub mod pdf;
 /// Unix process utilities.
 pub mod process;
-/// Amazon S3 utilities.
-pub mod s3;
+/// Sandboxing of the programs run for the documents.
+pub mod sandbox;
+/// Signing of the callback requests and of the file URLs.
+pub mod signing;
 /// Templating utilities.
 pub mod templating;
@@ -0,0 +1,138 @@
+use crate::prelude::*;
+use crate::utils::process::output_with_limits;
+use crate::utils::sandbox;
+use sha2::{Digest, Sha256};
+use slog::{debug, warn, Logger};
+use std::io::Read;
+use std::path::Path;
+
+/// What papers reports about the documents it produces.
+#[derive(Debug)]
+pub struct PdfInfo {
+    /// The name of the file.
+    pub filename: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The hex-encoded SHA-256 of the file.
+    pub sha256: String,
+    /// The number of pages, if `pdfinfo` could tell.
+    pub pages: Option<u32>,
+}
+
+/// Gather the [`PdfInfo`](PdfInfo) for the PDF at `path`.
+pub async fn inspect<'a>(
+    logger: &'a Logger,
+    config: &'a Config,
+    path: &'a Path,
+) -> Result<PdfInfo, failure::Error> {
+    let filename = path
+        .file_name()
+        .ok_or_else(|| format_err!("missing filename in \"{}\"", path.to_string_lossy()))?
+        .to_string_lossy()
+        .into_owned();
+
+    let (size, sha256) = hash_file(path).context("Error reading the generated PDF")?;
+
+    let pages = match page_count(config, path).await {
+        Ok(pages) => Some(pages),
+        Err(err) => {
+            warn!(logger, "Could not count the pages of {:?}: {:?}.", path, err);
+            None
+        }
+    };
+
+    debug!(
+        logger,
+        "{} is {} bytes long, has {:?} pages and SHA-256 {}.", filename, size, pages, sha256
+    );
+
+    Ok(PdfInfo {
+        filename,
+        size,
+        sha256,
+        pages,
+    })
+}
+
+/// Returns the size and the hex-encoded SHA-256 of a file, reading it in chunks.
+fn hash_file(path: &Path) -> Result<(u64, String), std::io::Error> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.input(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok((size, hex::encode(hasher.result())))
+}
+
+/// Count the pages of a PDF with poppler's `pdfinfo`, which runs in the sandbox of the
+/// directory of the PDF.
+async fn page_count(config: &Config, path: &Path) -> Result<u32, failure::Error> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| format_err!("missing directory in \"{}\"", path.to_string_lossy()))?;
+    let mut command = sandbox::command(config.sandbox.as_ref(), "pdfinfo", dir);
+    command.arg(path);
+    let output = output_with_limits(
+        command,
+        "pdfinfo",
+        config.pdfinfo_timeout,
+        config.resource_limits,
+    )
+    .await?;
+
+    let stdout_and_err = crate::utils::process::whole_output(&output)?;
+
+    if !output.status.success() {
+        return Err(format_err!("pdfinfo failed. Output:\n{}", stdout_and_err));
+    }
+
+    parse_page_count(&stdout_and_err)
+        .ok_or_else(|| format_err!("No page count in pdfinfo output:\n{}", stdout_and_err))
+}
+
+fn parse_page_count(pdfinfo_output: &str) -> Option<u32> {
+    pdfinfo_output
+        .lines()
+        .find(|line| line.starts_with("Pages:"))
+        .and_then(|line| line["Pages:".len()..].trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_page_count_works() {
+        let output = "Creator:        LaTeX with hyperref\n\
+                      Producer:       xdvipdfmx (20180506)\n\
+                      Pages:          12\n\
+                      Encrypted:      no\n";
+
+        assert_eq!(parse_page_count(output), Some(12));
+        assert_eq!(parse_page_count("Syntax Error: Couldn't read xref table"), None);
+    }
+
+    #[test]
+    fn hash_file_works() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let path = dir.as_ref().join("doc.pdf");
+        std::fs::write(&path, "abc").unwrap();
+
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            (
+                3,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_owned()
+            )
+        );
+    }
+}
@@ -1,5 +1,13 @@
-use std::process::Output;
+use crate::prelude::*;
+use failure::Fail;
+use futures::compat::*;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt as _;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 use std::str;
+use std::time::{Duration, Instant};
+use tokio_process::CommandExt;
 
 /// Merges the stdout and stderr of a process into a `String`.
 pub fn whole_output(output: &Output) -> Result<String, str::Utf8Error> {
@@ -8,10 +16,157 @@ pub fn whole_output(output: &Output) -> Result<String, str::Utf8Error> {
     Ok(format!("{}\n{}", stdout_str, stderr_str))
 }
 
+/// The path of `program`, looked up in the `PATH` when it is only a name, if it is an
+/// executable file.
+pub fn find_program(program: &Path) -> Option<PathBuf> {
+    let is_executable = |path: &Path| {
+        path.metadata()
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    };
+
+    if program.components().count() > 1 {
+        return Some(program.to_owned()).filter(|path| is_executable(path));
+    }
+
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|path| is_executable(path))
+    })
+}
+
+/// The error of a program that was killed because it ran longer than its timeout.
+#[derive(Debug, Fail)]
+#[fail(display = "{} timed out after {:?}", program, timeout)]
+pub struct TimedOut {
+    /// The name of the program.
+    pub program: String,
+    /// The timeout.
+    pub timeout: Duration,
+}
+
+/// The resource limits (`setrlimit`) of the programs papers runs. The limits that are not set
+/// are inherited from papers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// The CPU time, in seconds.
+    pub cpu_seconds: Option<u64>,
+    /// The size of the virtual memory, in bytes.
+    pub memory: Option<u64>,
+    /// The size of the files the program writes, in bytes.
+    pub file_size: Option<u64>,
+    /// The number of open files.
+    pub open_files: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Apply the limits to the current process. This runs in the child, between `fork` and
+    /// `exec`, so it only makes system calls.
+    fn apply(&self) -> std::io::Result<()> {
+        let limits = [
+            (libc::RLIMIT_CPU, self.cpu_seconds),
+            (libc::RLIMIT_AS, self.memory),
+            (libc::RLIMIT_FSIZE, self.file_size),
+            (libc::RLIMIT_NOFILE, self.open_files),
+        ];
+
+        for &(resource, limit) in &limits {
+            if let Some(limit) = limit {
+                let limit = libc::rlimit {
+                    rlim_cur: limit as libc::rlim_t,
+                    rlim_max: limit as libc::rlim_t,
+                };
+
+                if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Kills a process group when dropped, unless its leader was reaped. The group is then gone,
+/// or its id is still reserved by the programs left in it.
+struct ProcessGroup {
+    pid: libc::pid_t,
+    reaped: bool,
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if !self.reaped {
+            // The negative pid stands for the process group.
+            unsafe {
+                libc::kill(-self.pid, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Run `command` to completion and collect its output, like
+/// [`Command::output`](std::process::Command::output).
+///
+/// The program runs in its own process group with the resource `limits`. When it runs longer
+/// than `timeout`, the whole group is killed, so the programs it started do not linger, and the
+/// error is a [`TimedOut`](TimedOut). The group is killed as well when the future is dropped
+/// before the program exits, like when a job is cancelled.
+pub async fn output_with_limits(
+    mut command: Command,
+    program: &str,
+    timeout: Duration,
+    limits: ResourceLimits,
+) -> Result<Output, failure::Error> {
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            limits.apply()
+        });
+    }
+
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn_async()
+        .with_context(|_| format!("Error running {}", program))?;
+    let mut group = ProcessGroup {
+        pid: child.id() as libc::pid_t,
+        reaped: false,
+    };
+
+    let output = tokio::timer::Timeout::new_at(child.wait_with_output(), Instant::now() + timeout)
+        .compat()
+        .await;
+    // Otherwise, the group is killed on the way out.
+    group.reaped = output.is_ok();
+
+    output.map_err(|err| {
+        if err.is_elapsed() {
+            TimedOut {
+                program: program.to_owned(),
+                timeout,
+            }
+            .into()
+        } else {
+            match err.into_inner() {
+                Some(err) => failure::Error::from(err)
+                    .context(format!("Error running {}", program))
+                    .into(),
+                None => format_err!("Timer error while running {}", program),
+            }
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::process::Command;
+    use futures::{FutureExt, TryFutureExt};
 
     #[test]
     fn whole_output_works() {
@@ -25,4 +180,103 @@ mod tests {
         assert!(result.contains("nonexistent.exe"));
         assert!(result.contains("No such file"));
     }
+
+    fn run(
+        command: Command,
+        timeout: Duration,
+        limits: ResourceLimits,
+    ) -> Result<Output, failure::Error> {
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(
+                output_with_limits(command, "sh", timeout, limits)
+                    .boxed_local()
+                    .compat(),
+            )
+    }
+
+    #[test]
+    fn output_with_limits_kills_the_process_group_on_timeout() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let marker = dir.as_ref().join("marker");
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("(sleep 2; touch marker) & sleep 5")
+            .current_dir(&dir);
+
+        let err = run(
+            command,
+            Duration::from_millis(500),
+            ResourceLimits::default(),
+        )
+        .unwrap_err();
+
+        assert!(err.downcast_ref::<TimedOut>().is_some());
+        assert_eq!(err.to_string(), "sh timed out after 500ms");
+
+        // The background job was in the group too, so it never creates the marker.
+        std::thread::sleep(Duration::from_secs(3));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn output_with_limits_kills_the_process_group_when_dropped() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let marker = dir.as_ref().join("marker");
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("(sleep 2; touch marker) & sleep 5")
+            .current_dir(&dir);
+        let running = output_with_limits(
+            command,
+            "sh",
+            Duration::from_secs(60),
+            ResourceLimits::default(),
+        );
+
+        // Like a cancelled job, whose future is dropped while the program runs.
+        let err = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(tokio::timer::Timeout::new(
+                running.boxed_local().compat(),
+                Duration::from_millis(500),
+            ))
+            .unwrap_err();
+        assert!(err.is_elapsed());
+
+        std::thread::sleep(Duration::from_secs(3));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn find_program_looks_in_the_path() {
+        assert!(find_program(Path::new("sh")).unwrap().is_absolute());
+        assert_eq!(
+            find_program(Path::new("/bin/sh")),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        assert_eq!(find_program(Path::new("papers-missing-program")), None);
+
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let not_executable = dir.as_ref().join("script");
+        std::fs::write(&not_executable, "").unwrap();
+        assert_eq!(find_program(&not_executable), None);
+    }
+
+    #[test]
+    fn output_with_limits_applies_the_limits() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("ulimit -n; ulimit -t");
+        let limits = ResourceLimits {
+            cpu_seconds: Some(7),
+            open_files: Some(32),
+            ..ResourceLimits::default()
+        };
+
+        let output = run(command, Duration::from_secs(10), limits).unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "32\n7\n");
+    }
 }
@@ -0,0 +1,188 @@
+use crate::prelude::*;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What the sandboxed programs can read, unless `PAPERS_SANDBOX_READ_ONLY` says otherwise: the
+/// binaries and libraries, the TeX tree with its generated formats, the fonts and the
+/// configuration of TeX and ImageMagick.
+const READ_ONLY_DEFAULT: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib64",
+    "/etc/alternatives",
+    "/etc/ld.so.cache",
+    "/etc/fonts",
+    "/etc/texmf",
+    "/etc/ImageMagick-6",
+    "/var/lib/texmf",
+    "/var/cache/fontconfig",
+];
+
+/// Runs the programs started for the documents, LaTeX and its tools, `pdfunite`, `convert`,
+/// `pdfinfo` and the encryption of the workspace, with bubblewrap, in their own user, mount, PID
+/// and network namespaces.
+///
+/// Only the workspace is writable. The read-only paths are mounted as they are, the rest of
+/// the file system is not there, and there is no network.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sandbox {
+    /// The bubblewrap binary, looked up in the `PATH` when it is not a path.
+    pub program: PathBuf,
+    /// The paths the programs can read. The missing ones are skipped.
+    pub read_only: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// Read `PAPERS_SANDBOX`, `PAPERS_BWRAP_PATH` and `PAPERS_SANDBOX_READ_ONLY`, a
+    /// colon-separated list of paths. The programs are not sandboxed unless `PAPERS_SANDBOX`
+    /// is `bwrap`.
+    pub fn from_env() -> Result<Option<Self>, failure::Error> {
+        match std::env::var("PAPERS_SANDBOX").as_ref().map(String::as_str) {
+            Err(_) | Ok("none") => return Ok(None),
+            Ok("bwrap") => (),
+            Ok(other) => {
+                return Err(format_err!(
+                    "Unknown PAPERS_SANDBOX {:?}, expected none or bwrap",
+                    other
+                ))
+            }
+        }
+
+        let program = std::env::var("PAPERS_BWRAP_PATH").unwrap_or_else(|_| "bwrap".to_owned());
+        let read_only: Vec<PathBuf> = match std::env::var("PAPERS_SANDBOX_READ_ONLY") {
+            Ok(paths) => std::env::split_paths(&paths)
+                .filter(|path| !path.as_os_str().is_empty())
+                .collect(),
+            Err(_) => READ_ONLY_DEFAULT.iter().map(PathBuf::from).collect(),
+        };
+
+        if let Some(path) = read_only.iter().find(|path| path.is_relative()) {
+            return Err(format_err!(
+                "PAPERS_SANDBOX_READ_ONLY should only have absolute paths, got {:?}",
+                path
+            ));
+        }
+
+        Ok(Some(Sandbox {
+            program: PathBuf::from(program),
+            read_only,
+        }))
+    }
+
+    /// The command running `program` in the sandbox, in `workspace`, where it can also read
+    /// the `inputs`. The arguments of the program are added to the command.
+    pub fn command<S: AsRef<OsStr>>(
+        &self,
+        program: S,
+        workspace: &Path,
+        inputs: &[&Path],
+    ) -> Command {
+        let mut command = Command::new(&self.program);
+        command
+            .current_dir(workspace)
+            .args(self.args(program.as_ref(), workspace, inputs));
+        command
+    }
+
+    fn args(&self, program: &OsStr, workspace: &Path, inputs: &[&Path]) -> Vec<OsString> {
+        // The PID namespace goes away with bwrap, and everything started in it.
+        let mut args: Vec<OsString> = ["--unshare-all", "--die-with-parent", "--new-session"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+
+        for path in &self.read_only {
+            args.push("--ro-bind-try".into());
+            args.push(path.into());
+            args.push(path.into());
+        }
+
+        args.extend(
+            ["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"]
+                .iter()
+                .map(OsString::from),
+        );
+        args.push("--bind".into());
+        args.push(workspace.into());
+        args.push(workspace.into());
+        // After the temporary directory, which would hide the inputs kept in it.
+        for input in inputs {
+            args.push("--ro-bind".into());
+            args.push(input.into());
+            args.push(input.into());
+        }
+        args.push("--chdir".into());
+        args.push(workspace.into());
+        // TeX writes its caches in the home directory, which would not be writable otherwise.
+        args.push("--setenv".into());
+        args.push("HOME".into());
+        args.push(workspace.into());
+        args.push("--".into());
+        args.push(program.into());
+        args
+    }
+}
+
+/// The command running `program` in `workspace`, in the sandbox when there is one.
+pub fn command<S: AsRef<OsStr>>(
+    sandbox: Option<&Sandbox>,
+    program: S,
+    workspace: &Path,
+) -> Command {
+    command_reading(sandbox, program, workspace, &[])
+}
+
+/// Like [`command`](command), for a program that also reads `inputs`, which are outside of
+/// `workspace`.
+pub fn command_reading<S: AsRef<OsStr>>(
+    sandbox: Option<&Sandbox>,
+    program: S,
+    workspace: &Path,
+    inputs: &[&Path],
+) -> Command {
+    match sandbox {
+        Some(sandbox) => sandbox.command(program, workspace, inputs),
+        None => {
+            let mut command = Command::new(program);
+            command.current_dir(workspace);
+            command
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_workspace_is_writable() {
+        let sandbox = Sandbox {
+            program: PathBuf::from("bwrap"),
+            read_only: vec![PathBuf::from("/usr")],
+        };
+        let args = sandbox.args(
+            OsStr::new("gpg"),
+            Path::new("/tmp/job"),
+            &[Path::new("/tmp/workspace.tar")],
+        );
+
+        assert_eq!(args[0], "--unshare-all");
+        assert!(args
+            .windows(3)
+            .any(|args| args == ["--ro-bind-try", "/usr", "/usr"]));
+        assert!(args
+            .windows(3)
+            .any(|args| args == ["--bind", "/tmp/job", "/tmp/job"]));
+        let bind = args.iter().position(|arg| arg == "--bind").unwrap();
+        let tmpfs = args.iter().position(|arg| arg == "--tmpfs").unwrap();
+        let input = args
+            .windows(3)
+            .position(|args| args == ["--ro-bind", "/tmp/workspace.tar", "/tmp/workspace.tar"])
+            .unwrap();
+        assert!(tmpfs < bind && tmpfs < input);
+        assert_eq!(args[args.len() - 2..], ["--", "gpg"]);
+    }
+}
@@ -1,5 +1,7 @@
 mod toolbox;
 
+use papers::storage::KeyPattern;
+use papers::Config;
 use toolbox::*;
 
 #[test]
@@ -28,3 +30,69 @@ fn test_404() {
 
     assert_eq!(response.status(), 404);
 }
+
+#[test]
+fn test_unknown_job_is_404() {
+    let test_setup = TestSetup::start_default();
+
+    let response = test_setup
+        .client()
+        .get(&test_setup.papers_url("jobs/7f9e8c4a-3b1d-4e5f-9a2b-6c8d0e1f2a3b"))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), 404);
+}
+
+#[test]
+fn test_cancel_unknown_job_is_404() {
+    let test_setup = TestSetup::start_default();
+
+    let response = test_setup
+        .client()
+        .delete(&test_setup.papers_url("jobs/7f9e8c4a-3b1d-4e5f-9a2b-6c8d0e1f2a3b"))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), 404);
+}
+
+#[test]
+fn test_render_with_an_unreachable_template_is_422() {
+    let test_setup = TestSetup::start_default();
+
+    let response = test_setup
+        .client()
+        .post(&test_setup.papers_url("render"))
+        .json(&serde_json::json!({ "template_url": "http://127.0.0.1:1/template" }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), 422);
+}
+
+#[test]
+fn test_render_ignores_the_metadata_of_the_key_pattern() {
+    let pattern = KeyPattern::parse("{tenant}/{yyyy}/{mm}/{job_id}/{output_filename}").unwrap();
+    let mut test_setup_config = TestSetupConfig::default();
+    test_setup_config.set_config(Config::for_tests().with_key_pattern(pattern));
+    test_setup_config.serve_files();
+    let test_setup = TestSetup::start(test_setup_config);
+
+    std::fs::write(
+        test_setup.files_dir().join("template"),
+        "\\documentclass{article}\\begin{document}hello\\end{document}",
+    )
+    .unwrap();
+
+    // There is no tenant in the metadata, which only matters for uploaded files.
+    let response = test_setup
+        .client()
+        .post(&test_setup.papers_url("render"))
+        .json(&serde_json::json!({ "template_url": test_setup.files_server_url("template") }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/pdf");
+}
@@ -0,0 +1,53 @@
+use crate::toolbox::*;
+use serde_json::json;
+
+const TEMPLATE: &'static str = r"
+\documentclass{article}
+
+\begin{document}
+hello, {{who}}
+
+\end{document}
+";
+
+pub fn test_end_to_end() {
+    use std::io::{Read, Write};
+
+    let mut test_config = TestSetupConfig::default();
+
+    test_config.serve_files();
+
+    let mut test_setup = TestSetup::start(test_config);
+
+    let mut template_file = std::fs::File::create(test_setup.files_dir().join("template")).unwrap();
+    write!(template_file, "{}", TEMPLATE).unwrap();
+
+    let render_spec = json!({
+        "template_url": test_setup.files_server_url("template"),
+        "output_filename": "hello.pdf",
+        "variables": {
+            "who": "peter"
+        }
+    });
+
+    let mut response = test_setup
+        .client()
+        .post(&test_setup.papers_url("render"))
+        .json(&render_spec)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/pdf");
+    assert_eq!(
+        response.headers()["content-disposition"],
+        "inline; filename=\"hello.pdf\""
+    );
+
+    let mut pdf = Vec::new();
+    response.read_to_end(&mut pdf).unwrap();
+    assert!(pdf.starts_with(b"%PDF"));
+
+    let expected_files_requests = vec![(http::Method::GET, "/template".to_owned())];
+    assert_eq!(test_setup.files_requests(), expected_files_requests);
+}
@@ -0,0 +1 @@
+pub mod end_to_end;
@@ -11,7 +11,7 @@ hello, {{who}}
 ";
 
 pub fn test_end_to_end() {
-    use std::io::Write;
+    use std::io::{Read, Write};
 
     let mut test_config = TestSetupConfig::default();
 
@@ -41,7 +41,7 @@ pub fn test_end_to_end() {
         }
     });
 
-    let response = test_setup
+    let mut response = test_setup
         .client()
         .post(&test_setup.papers_url("submit"))
         .json(&document_spec)
@@ -50,6 +50,9 @@ pub fn test_end_to_end() {
 
     assert_eq!(response.status(), 200);
 
+    let body: serde_json::Value = response.json().unwrap();
+    let job_id = body["job_id"].as_str().expect("job_id in response").to_owned();
+
     // Leave one second to the background job to finish.
     std::thread::sleep(std::time::Duration::from_secs(1));
 
@@ -61,4 +64,28 @@ pub fn test_end_to_end() {
         (http::Method::GET, "/logo.png".to_owned()),
     ];
     assert_eq!(test_setup.files_requests(), expected_files_requests);
+
+    let mut job_response = test_setup
+        .client()
+        .get(&test_setup.papers_url(&format!("jobs/{}", job_id)))
+        .send()
+        .unwrap();
+
+    assert_eq!(job_response.status(), 200);
+
+    let job_status: serde_json::Value = job_response.json().unwrap();
+    assert_eq!(job_status["id"], job_id.as_str());
+    assert!(job_status["summary"].is_object());
+    assert_eq!(job_status["summary"]["job_id"], job_id.as_str());
+    assert_eq!(job_status["summary"]["schema_version"], 2);
+
+    // The PDF is served by papers itself.
+    let file_url = job_status["summary"]["file"].as_str().expect("file in summary");
+    let mut file_response = test_setup.client().get(file_url).send().unwrap();
+    assert_eq!(file_response.status(), 200);
+    assert_eq!(file_response.headers()["content-type"], "application/pdf");
+
+    let mut pdf = Vec::new();
+    file_response.read_to_end(&mut pdf).unwrap();
+    assert!(pdf.starts_with(b"%PDF"));
 }
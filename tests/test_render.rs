@@ -0,0 +1,7 @@
+mod render;
+mod toolbox;
+
+#[test]
+fn test_render() {
+    render::end_to_end::test_end_to_end();
+}
@@ -30,6 +30,8 @@ pub struct CallbackServer {
 struct PapersServer {
     config: Arc<papers::Config>,
     port: u16,
+    /// Where the default local storage keeps the outputs.
+    _storage_dir: mktemp::Temp,
     _thread: std::thread::JoinHandle<()>,
 }
 
@@ -55,11 +57,22 @@ impl TestSetup {
         } = config;
 
         let papers_port = random_port();
-        let config = Arc::new(config.unwrap_or_else(|| papers::Config::for_tests()));
+        let storage_dir = mktemp::Temp::new_dir().unwrap();
+        // Outputs are stored locally and served by papers, so no S3 is needed.
+        let config = Arc::new(config.unwrap_or_else(|| {
+            let storage = papers::storage::LocalStorage::new(
+                storage_dir.to_path_buf(),
+                &format!("http://localhost:{}", papers_port),
+                "test-secret".to_owned(),
+            )
+            .expect("creating the local storage");
+            papers::Config::for_tests().with_storage(Arc::new(storage))
+        }));
         let config_handle = config.clone();
+        let jobs = papers::Jobs::new(config.clone()).expect("creating the jobs registry");
 
         let papers_thread_handle = std::thread::spawn(move || {
-            warp::serve(papers::app(config_handle)).run(([0, 0, 0, 0], papers_port))
+            warp::serve(papers::app(config_handle, jobs)).run(([0, 0, 0, 0], papers_port))
         });
 
         let files_server = if serve_files {
@@ -106,6 +119,7 @@ impl TestSetup {
                 _thread: papers_thread_handle,
                 config,
                 port: papers_port,
+                _storage_dir: storage_dir,
             },
             files_server,
         }